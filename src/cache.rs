@@ -0,0 +1,605 @@
+use log::{debug, error};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+/// Queries answered from the cached state
+const CACHED_COMMANDS: [&str; 5] = ["workspaces", "activeworkspace", "monitors", "clients", "activewindow"];
+
+/// State of the compositor, in the format of "hyprctl -j" outputs,
+/// seeded by a full resync and updated from the events in between.
+struct Cache {
+    workspaces: Vec<Value>,
+    monitors: Vec<Value>,
+    clients: Vec<Value>,
+    /// address of the focused window, empty if none
+    active_window: String,
+    /// class and title of "activewindow", applied by the "activewindowv2" after it
+    pending_window: Option<(String, String)>,
+    /// set when an event could not be applied, the next query resyncs
+    dirty: bool,
+    /// set when an event is dropped (or the cache marked dirty) while resyncing,
+    /// the snapshot may predate it so the cache stays dirty
+    missed: bool,
+}
+
+static CACHE: Mutex<Option<Cache>> = Mutex::new(None);
+
+/// Enables the cache, it is seeded by the first query.
+pub(crate) fn enable() {
+    CACHE.lock().unwrap().replace(Cache {
+        workspaces: vec![],
+        monitors: vec![],
+        clients: vec![],
+        active_window: String::new(),
+        pending_window: None,
+        dirty: true,
+        missed: false,
+    });
+}
+
+/// Returns true if the command is answered by the cache and it needs a resync first.
+pub(crate) fn needs_resync(command: &str) -> bool {
+    match CACHE.lock().unwrap().as_ref() {
+        Some(cache) => cache.dirty && CACHED_COMMANDS.contains(&command),
+        None => false,
+    }
+}
+
+/// Forces a full resync on the next query (drift correction).
+pub(crate) fn mark_dirty() {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        cache.dirty = true;
+        cache.missed = true;
+    }
+}
+
+/// To call before querying hyprctl for `resync`
+pub(crate) fn begin_resync() {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        cache.missed = false;
+    }
+}
+
+/// Replaces the cached state with fresh "hyprctl -j" outputs,
+/// it stays dirty if events were dropped since `begin_resync`.
+pub(crate) fn resync(workspaces: Value, monitors: Value, clients: Value, active_window: Value) {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        match (workspaces, monitors, clients) {
+            (Value::Array(workspaces), Value::Array(monitors), Value::Array(clients)) => {
+                cache.workspaces = workspaces;
+                cache.monitors = monitors;
+                cache.clients = clients;
+                cache.active_window = String::from(get_str(&active_window, "address"));
+                cache.pending_window = None;
+                if cache.missed {
+                    debug!("events received while resyncing the cache, it stays dirty");
+                }
+                cache.dirty = cache.missed;
+            }
+            _ => error!("could not resync cache, hyprctl outputs are not JSON arrays!"),
+        }
+    }
+}
+
+/// Returns the cached output of "hyprctl -j <command>",
+/// `None` if the cache is disabled, dirty or doesn't know the answer.
+pub(crate) fn query(command: &str) -> Option<Value> {
+    let guard = CACHE.lock().unwrap();
+    let cache = guard.as_ref()?;
+    if cache.dirty {
+        return None
+    }
+
+    match command {
+        "workspaces" => Some(Value::Array(cache.workspaces.clone())),
+        "monitors" => Some(Value::Array(cache.monitors.clone())),
+        "clients" => Some(Value::Array(cache.clients.clone())),
+        "activeworkspace" => {
+            let monitor = cache.monitors.iter().find(|m| m["focused"] == json!(true))?;
+            let id = monitor["activeWorkspace"]["id"].as_i64()?;
+            cache.workspaces.iter().find(|w| w["id"].as_i64() == Some(id)).cloned()
+        }
+        "activewindow" => Some(
+            cache
+                .clients
+                .iter()
+                .find(|c| get_str(c, "address") == cache.active_window)
+                .cloned()
+                .unwrap_or(json!({})),
+        ),
+        _ => None,
+    }
+}
+
+/// Updates the cached state from an event of the event socket
+/// (name and values as parsed by `events::parse_event`).
+pub(crate) fn apply(name: &str, data: &Value) {
+    if let Some(cache) = CACHE.lock().unwrap().as_mut() {
+        if cache.dirty {
+            cache.missed = true;
+        } else if !cache.apply(name, data) {
+            cache.dirty = true;
+        }
+    }
+}
+
+fn get_str<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+/// Returns the address the way "hyprctl -j clients" does (with "0x")
+fn hex_address(address: &str) -> String {
+    if address.starts_with("0x") {
+        String::from(address)
+    } else {
+        format!("0x{address}")
+    }
+}
+
+impl Cache {
+    fn workspace_by_name(&mut self, name: &str) -> Option<&mut Value> {
+        self.workspaces.iter_mut().find(|w| get_str(w, "name") == name)
+    }
+
+    fn workspace_by_id(&mut self, id: i64) -> Option<&mut Value> {
+        self.workspaces.iter_mut().find(|w| w["id"].as_i64() == Some(id))
+    }
+
+    /// Returns the id of the workspace named so, numbered workspaces are named after their id
+    fn workspace_id(&self, name: &str) -> Option<i64> {
+        match self.workspaces.iter().find(|w| get_str(w, "name") == name) {
+            Some(work) => work["id"].as_i64(),
+            None => name.parse().ok(),
+        }
+    }
+
+    fn focused_monitor(&mut self) -> Option<&mut Value> {
+        self.monitors.iter_mut().find(|m| m["focused"] == json!(true))
+    }
+
+    fn client(&mut self, address: &str) -> Option<&mut Value> {
+        self.clients.iter_mut().find(|c| get_str(c, "address") == address)
+    }
+
+    /// Adds the count of windows to the workspace and updates its last window
+    fn count_window(&mut self, workspace_id: i64, count: i64) {
+        let last_client = self
+            .clients
+            .iter()
+            .rev()
+            .find(|c| c["workspace"]["id"].as_i64() == Some(workspace_id))
+            .map(|c| (String::from(get_str(c, "address")), String::from(get_str(c, "title"))));
+
+        if let Some(work) = self.workspace_by_id(workspace_id) {
+            let windows = work["windows"].as_i64().unwrap_or(0) + count;
+            work["windows"] = json!(windows.max(0));
+            let (address, title) = last_client.unwrap_or((String::from("0x0"), String::new()));
+            work["lastwindow"] = json!(address);
+            work["lastwindowtitle"] = json!(title);
+        }
+    }
+
+    fn create_workspace(&mut self, id: i64, name: &str) -> bool {
+        if self.workspace_by_id(id).is_some() {
+            return true
+        }
+        let Some(monitor) = self.focused_monitor() else { return false };
+        let work = json!({
+            "id": id,
+            "name": name,
+            "monitor": monitor["name"],
+            "monitorID": monitor["id"],
+            "windows": 0,
+            "hasfullscreen": false,
+            "lastwindow": "0x0",
+            "lastwindowtitle": "",
+        });
+        self.workspaces.push(work);
+        true
+    }
+
+    fn move_window(&mut self, address: &str, id: i64, name: &str) -> bool {
+        let Some(client) = self.client(address) else { return false };
+        let old_id = client["workspace"]["id"].as_i64();
+        client["workspace"] = json!({ "id": id, "name": name });
+        if let Some(old_id) = old_id {
+            self.count_window(old_id, -1);
+        }
+        self.count_window(id, 1);
+        true
+    }
+
+    /// Returns false if the event could not be applied and a resync is needed
+    fn apply(&mut self, name: &str, data: &Value) -> bool {
+        match name {
+            // focused workspace on the focused monitor
+            "workspace" => {
+                let work_name = get_str(data, "name");
+                let Some(id) = self.workspace_id(work_name) else { return false };
+                match self.focused_monitor() {
+                    Some(monitor) => {
+                        monitor["activeWorkspace"] = json!({ "id": id, "name": work_name });
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "focusedmon" => {
+                let monitor_name = get_str(data, "monitor");
+                let work_name = get_str(data, "workspace");
+                let id = self.workspace_id(work_name);
+                let mut found = false;
+                for monitor in self.monitors.iter_mut() {
+                    let focused = get_str(monitor, "name") == monitor_name;
+                    monitor["focused"] = json!(focused);
+                    if focused {
+                        found = true;
+                        if let Some(id) = id {
+                            monitor["activeWorkspace"] = json!({ "id": id, "name": work_name });
+                        }
+                    }
+                }
+                found && id.is_some()
+            }
+            "workspacev2" => {
+                let Ok(id) = get_str(data, "id").parse::<i64>() else { return false };
+                let work_name = get_str(data, "name");
+                match self.focused_monitor() {
+                    Some(monitor) => {
+                        monitor["activeWorkspace"] = json!({ "id": id, "name": work_name });
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "createworkspace" => {
+                let work_name = get_str(data, "name");
+                if self.workspace_by_name(work_name).is_some() {
+                    return true
+                }
+                // only numbered workspaces have an id we can guess
+                match work_name.parse::<i64>() {
+                    Ok(id) => self.create_workspace(id, work_name),
+                    Err(_) => false,
+                }
+            }
+            "createworkspacev2" => {
+                let Ok(id) = get_str(data, "id").parse::<i64>() else { return false };
+                self.create_workspace(id, get_str(data, "name"))
+            }
+            "destroyworkspace" => {
+                let work_name = get_str(data, "name");
+                self.workspaces.retain(|w| get_str(w, "name") != work_name);
+                true
+            }
+            "destroyworkspacev2" => {
+                let Ok(id) = get_str(data, "id").parse::<i64>() else { return false };
+                self.workspaces.retain(|w| w["id"].as_i64() != Some(id));
+                true
+            }
+            "moveworkspacev2" => {
+                let Ok(id) = get_str(data, "id").parse::<i64>() else { return false };
+                let monitor_name = get_str(data, "monitor");
+                let Some(monitor) = self.monitors.iter().find(|m| get_str(m, "name") == monitor_name) else {
+                    return false
+                };
+                let monitor_id = monitor["id"].clone();
+                match self.workspace_by_id(id) {
+                    Some(work) => {
+                        work["monitor"] = json!(monitor_name);
+                        work["monitorID"] = monitor_id;
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "renameworkspace" => {
+                let Ok(id) = get_str(data, "id").parse::<i64>() else { return false };
+                let work_name = get_str(data, "name");
+                match self.workspace_by_id(id) {
+                    Some(work) => {
+                        work["name"] = json!(work_name);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "activespecial" => {
+                let work_name = get_str(data, "name");
+                let monitor_name = get_str(data, "monitor");
+                let id = if work_name.is_empty() { Some(0) } else { self.workspace_id(work_name) };
+                let Some(id) = id else { return false };
+                match self.monitors.iter_mut().find(|m| get_str(m, "name") == monitor_name) {
+                    Some(monitor) => {
+                        monitor["specialWorkspace"] = json!({ "id": id, "name": work_name });
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "openwindow" => {
+                let address = hex_address(get_str(data, "address"));
+                let work_name = get_str(data, "workspace");
+                let Some(id) = self.workspace_id(work_name) else { return false };
+                let Some(work) = self.workspace_by_id(id) else { return false };
+                let monitor_id = work["monitorID"].clone();
+                let client = json!({
+                    "address": address,
+                    "mapped": true,
+                    "hidden": false,
+                    "workspace": { "id": id, "name": work_name },
+                    "floating": false,
+                    "fullscreen": false,
+                    "monitor": monitor_id,
+                    "class": get_str(data, "class"),
+                    "title": get_str(data, "title"),
+                    "initialClass": get_str(data, "class"),
+                    "initialTitle": get_str(data, "title"),
+                    "pinned": false,
+                });
+                self.clients.push(client);
+                self.count_window(id, 1);
+                true
+            }
+            "closewindow" => {
+                let address = hex_address(get_str(data, "address"));
+                let Some(client) = self.client(&address) else { return true };
+                let id = client["workspace"]["id"].as_i64();
+                self.clients.retain(|c| get_str(c, "address") != address);
+                if self.active_window == address {
+                    self.active_window = String::new();
+                }
+                if let Some(id) = id {
+                    self.count_window(id, -1);
+                }
+                true
+            }
+            "movewindow" => {
+                let address = hex_address(get_str(data, "address"));
+                let work_name = get_str(data, "workspace");
+                match self.workspace_id(work_name) {
+                    Some(id) => self.move_window(&address, id, work_name),
+                    None => false,
+                }
+            }
+            "movewindowv2" => {
+                let address = hex_address(get_str(data, "address"));
+                let Ok(id) = get_str(data, "id").parse::<i64>() else { return false };
+                self.move_window(&address, id, get_str(data, "name"))
+            }
+            // sent before "activewindowv2", which tells the window it is about
+            "activewindow" => {
+                let class = String::from(get_str(data, "class"));
+                self.pending_window = Some((class, String::from(get_str(data, "title"))));
+                true
+            }
+            "activewindowv2" => {
+                let address = get_str(data, "address");
+                self.active_window = if address.is_empty() || address == "," {
+                    String::new()
+                } else {
+                    hex_address(address)
+                };
+                // also sent when the title of the focused window changes
+                let Some((class, title)) = self.pending_window.take() else { return true };
+                let address = self.active_window.clone();
+                if let Some(client) = self.client(&address) {
+                    client["class"] = json!(class);
+                    client["title"] = json!(title);
+                    let id = client["workspace"]["id"].as_i64();
+                    if let Some(work) = id.and_then(|id| self.workspace_by_id(id)) {
+                        work["lastwindow"] = json!(address);
+                        work["lastwindowtitle"] = json!(title);
+                    }
+                }
+                true
+            }
+            "changefloatingmode" => {
+                let address = hex_address(get_str(data, "address"));
+                let floating = get_str(data, "floating") == "1";
+                match self.client(&address) {
+                    Some(client) => {
+                        client["floating"] = json!(floating);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "fullscreen" => {
+                let fullscreen = get_str(data, "state") == "1";
+                let address = self.active_window.clone();
+                let Some(client) = self.client(&address) else { return false };
+                client["fullscreen"] = json!(fullscreen);
+                let id = client["workspace"]["id"].as_i64();
+                match id.and_then(|id| self.workspace_by_id(id)) {
+                    Some(work) => {
+                        work["hasfullscreen"] = json!(fullscreen);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            "windowtitlev2" => {
+                let address = hex_address(get_str(data, "address"));
+                let title = String::from(get_str(data, "title"));
+                let Some(client) = self.client(&address) else { return false };
+                client["title"] = json!(title);
+                let id = client["workspace"]["id"].as_i64();
+                if let Some(work) = id.and_then(|id| self.workspace_by_id(id)) {
+                    if get_str(work, "lastwindow") == address {
+                        work["lastwindowtitle"] = json!(title);
+                    }
+                }
+                true
+            }
+            // without v2 events, titles of unfocused windows are corrected by the periodic resync
+            "windowtitle" => true,
+            // need data the events don't carry
+            "monitoradded" | "monitoraddedv2" | "monitorremoved" | "moveworkspace" | "configreloaded" => false,
+            // doesn't change the cached state
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events;
+
+    /// One monitor focused on workspace 1, no window
+    fn new_cache() -> Cache {
+        Cache {
+            workspaces: vec![json!({
+                "id": 1, "name": "1", "monitor": "DP-1", "monitorID": 0, "windows": 0,
+                "hasfullscreen": false, "lastwindow": "0x0", "lastwindowtitle": "",
+            })],
+            monitors: vec![json!({
+                "id": 0, "name": "DP-1", "focused": true,
+                "activeWorkspace": { "id": 1, "name": "1" },
+                "specialWorkspace": { "id": 0, "name": "" },
+            })],
+            clients: vec![],
+            active_window: String::new(),
+            pending_window: None,
+            dirty: false,
+            missed: false,
+        }
+    }
+
+    /// Applies the lines of the event socket, returns false if one needs a resync
+    fn apply_lines(cache: &mut Cache, lines: &[&str]) -> bool {
+        lines.iter().all(|line| {
+            let (name, data) = events::parse_event(line).unwrap();
+            cache.apply(&name, &data)
+        })
+    }
+
+    fn workspace(cache: &Cache, id: i64) -> &Value {
+        cache.workspaces.iter().find(|w| w["id"].as_i64() == Some(id)).unwrap()
+    }
+
+    fn workspace_ids(cache: &Cache) -> Vec<i64> {
+        cache.workspaces.iter().filter_map(|w| w["id"].as_i64()).collect()
+    }
+
+    #[test]
+    fn window_counts_follow_open_move_close() {
+        let mut cache = new_cache();
+        assert!(apply_lines(&mut cache, &[
+            "openwindow>>a1,1,kitty,first",
+            "createworkspacev2>>2,2",
+            "openwindow>>a2,2,foot,second",
+            "movewindowv2>>a1,2,2",
+        ]));
+        assert_eq!(workspace(&cache, 1)["windows"], json!(0));
+        assert_eq!(workspace(&cache, 1)["lastwindow"], json!("0x0"));
+        assert_eq!(workspace(&cache, 2)["windows"], json!(2));
+        assert_eq!(workspace(&cache, 2)["monitor"], json!("DP-1"));
+        assert_eq!(cache.clients[0]["workspace"], json!({ "id": 2, "name": "2" }));
+
+        assert!(apply_lines(&mut cache, &["closewindow>>a2"]));
+        assert_eq!(workspace(&cache, 2)["windows"], json!(1));
+        assert_eq!(workspace(&cache, 2)["lastwindow"], json!("0xa1"));
+        assert_eq!(workspace(&cache, 2)["lastwindowtitle"], json!("first"));
+        assert_eq!(cache.clients.len(), 1);
+    }
+
+    #[test]
+    fn workspaces_follow_create_rename_destroy() {
+        let mut cache = new_cache();
+        assert!(apply_lines(&mut cache, &["createworkspace>>3", "createworkspacev2>>4,4"]));
+        assert_eq!(workspace_ids(&cache), vec![1, 3, 4]);
+
+        assert!(apply_lines(&mut cache, &["renameworkspace>>3,code"]));
+        assert_eq!(workspace(&cache, 3)["name"], json!("code"));
+
+        assert!(apply_lines(&mut cache, &["destroyworkspacev2>>3,code", "destroyworkspace>>4"]));
+        assert_eq!(workspace_ids(&cache), vec![1]);
+    }
+
+    #[test]
+    fn named_workspaces_are_resolved_by_name() {
+        let mut cache = new_cache();
+        assert!(apply_lines(&mut cache, &[
+            "createworkspacev2>>-1337,web",
+            "openwindow>>a1,web,firefox,page",
+            "workspace>>web",
+        ]));
+        assert_eq!(workspace(&cache, -1337)["windows"], json!(1));
+        assert_eq!(cache.clients[0]["workspace"], json!({ "id": -1337, "name": "web" }));
+        assert_eq!(cache.monitors[0]["activeWorkspace"], json!({ "id": -1337, "name": "web" }));
+
+        // a named workspace can't be created from the v1 event, its id is unknown
+        assert!(!apply_lines(&mut cache, &["createworkspace>>mail"]));
+    }
+
+    #[test]
+    fn v1_then_v2_duplicates_apply_once() {
+        let mut cache = new_cache();
+        assert!(apply_lines(&mut cache, &[
+            "openwindow>>a1,1,kitty,first",
+            "createworkspace>>4",
+            "createworkspacev2>>4,4",
+            "movewindow>>a1,4",
+            "movewindowv2>>a1,4,4",
+            "workspace>>4",
+            "workspacev2>>4,4",
+        ]));
+        assert_eq!(workspace_ids(&cache), vec![1, 4]);
+        assert_eq!(workspace(&cache, 1)["windows"], json!(0));
+        assert_eq!(workspace(&cache, 4)["windows"], json!(1));
+        assert_eq!(cache.monitors[0]["activeWorkspace"], json!({ "id": 4, "name": "4" }));
+    }
+
+    #[test]
+    fn active_window_is_updated_on_the_newly_focused_window() {
+        let mut cache = new_cache();
+        assert!(apply_lines(&mut cache, &[
+            "openwindow>>a1,1,kitty,a",
+            "openwindow>>a2,1,foot,b",
+            "activewindow>>kitty,a",
+            "activewindowv2>>a1",
+            "activewindow>>foot,b",
+            "activewindowv2>>a2",
+        ]));
+        assert_eq!(cache.active_window, "0xa2");
+        assert_eq!((&cache.clients[0]["class"], &cache.clients[0]["title"]), (&json!("kitty"), &json!("a")));
+        assert_eq!((&cache.clients[1]["class"], &cache.clients[1]["title"]), (&json!("foot"), &json!("b")));
+        assert_eq!(workspace(&cache, 1)["lastwindow"], json!("0xa2"));
+        assert_eq!(workspace(&cache, 1)["lastwindowtitle"], json!("b"));
+
+        // the title of the focused window changes
+        assert!(apply_lines(&mut cache, &["activewindow>>foot,c", "activewindowv2>>a2"]));
+        assert_eq!(cache.clients[1]["title"], json!("c"));
+        assert_eq!(cache.clients[0]["title"], json!("a"));
+    }
+
+    #[test]
+    fn unsupported_events_mark_dirty() {
+        let mut cache = new_cache();
+        assert!(!apply_lines(&mut cache, &["monitoraddedv2>>1,HDMI-A-1,Some screen"]));
+        assert!(!apply_lines(&mut cache, &["configreloaded>>"]));
+        // events which don't change the state are fine
+        assert!(apply_lines(&mut cache, &["openlayer>>bar"]));
+
+        // the only test using the global cache
+        enable();
+        begin_resync();
+        resync(json!(new_cache().workspaces), json!(new_cache().monitors), json!([]), json!({}));
+        assert!(!needs_resync("workspaces"));
+        apply("monitorremoved", &json!({ "monitor": "DP-1" }));
+        assert!(needs_resync("workspaces"));
+        assert_eq!(query("workspaces"), None);
+
+        // an event dropped while hyprctl is queried may be missing from the snapshot
+        begin_resync();
+        apply("workspacev2", &json!({ "id": "1", "name": "1" }));
+        resync(json!(new_cache().workspaces), json!(new_cache().monitors), json!([]), json!({}));
+        assert!(needs_resync("workspaces"));
+
+        begin_resync();
+        resync(json!(new_cache().workspaces), json!(new_cache().monitors), json!([]), json!({}));
+        assert!(!needs_resync("workspaces"));
+    }
+}
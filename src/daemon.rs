@@ -0,0 +1,211 @@
+use crate::router;
+use log::{debug, info, warn};
+use std::env;
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Write};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::fs::FileTypeExt;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Returns the path of the daemon's lock or socket file
+/// for the current Hyprland instance.
+pub(crate) fn get_daemon_path(file_name: &str) -> io::Result<PathBuf> {
+    crate::get_runtime_path(file_name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "XDG_RUNTIME_DIR is not set"))
+}
+
+/// Sends a state ("READY=1", "WATCHDOG=1", ...) to the service manager,
+/// does nothing if not started by systemd with NOTIFY_SOCKET.
+fn sd_notify(state: &str) {
+    let Ok(path) = env::var("NOTIFY_SOCKET") else { return };
+
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        match path.strip_prefix('@') {
+            Some(name) => socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?),
+            None => socket.send_to(state.as_bytes(), &path),
+        }
+    });
+    if let Err(e) = result {
+        warn!("Could not notify systemd ({}): {:?}", state, e);
+    }
+}
+
+/// Returns true if the variable is not set or set to our pid,
+/// as systemd does for LISTEN_PID and WATCHDOG_PID.
+fn is_for_us(var: &str) -> bool {
+    env::var(var).map_or(true, |pid| pid == process::id().to_string())
+}
+
+/// Pings the systemd watchdog at half the period given by WATCHDOG_USEC,
+/// unless a refresh has been stuck (e.g. on a hung hyprctl) for a whole period.
+fn start_watchdog() {
+    let Some(usec) = env::var("WATCHDOG_USEC").ok().and_then(|v| v.parse::<u64>().ok()) else { return };
+    if usec == 0 || !is_for_us("WATCHDOG_PID") {
+        return
+    }
+
+    debug!("Pinging the watchdog every {}us", usec / 2);
+    tokio::spawn(async move {
+        let period = Duration::from_micros(usec);
+        let mut interval = tokio::time::interval(period / 2);
+        loop {
+            interval.tick().await;
+            match router::get_refresh_duration() {
+                Some(duration) if duration >= period => warn!("Refresh stuck for {:?}, not pinging the watchdog", duration),
+                _ => sd_notify("WATCHDOG=1"),
+            }
+        }
+    });
+}
+
+/// Takes the lock of the Hyprland instance, fails if another daemon holds it.
+/// The lock is released when the returned file is dropped (or the process ends).
+fn lock() -> io::Result<File> {
+    let path = get_daemon_path("daemon.lock")?;
+    let mut file = File::options().create(true).truncate(false).write(true).open(&path)?;
+
+    match file.try_lock() {
+        Ok(()) => {},
+        Err(TryLockError::WouldBlock) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a daemon is already running for this Hyprland instance (lock {:?})", path),
+            ))
+        },
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+
+    file.set_len(0)?;
+    writeln!(file, "{}", process::id())?;
+    Ok(file)
+}
+
+/// Returns the client socket, "daemon.sock" in the runtime directory if not given
+fn get_socket_path(socket: &str) -> io::Result<PathBuf> {
+    if socket.is_empty() {
+        get_daemon_path("daemon.sock")
+    } else {
+        Ok(PathBuf::from(socket))
+    }
+}
+
+/// Returns the socket passed by systemd (a .socket unit with one ListenStream)
+/// or binds the client socket.
+fn get_listener(socket: &str) -> io::Result<UnixListener> {
+    let listen_fds = env::var("LISTEN_FDS").ok().and_then(|v| v.parse::<i32>().ok()).unwrap_or(0);
+    let passed = listen_fds > 0 && env::var("LISTEN_PID").is_ok() && is_for_us("LISTEN_PID");
+    // as sd_listen_fds(1) does, so hyprctl and eww don't think the socket is theirs
+    for var in ["LISTEN_FDS", "LISTEN_PID", "LISTEN_FDNAMES"] {
+        env::remove_var(var);
+    }
+
+    if passed {
+        info!("Using the socket passed by systemd");
+        // SAFETY: systemd passes the listening socket as our fd 3, nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+        // fd 3 is inherited by the children, its duplicate is close-on-exec
+        let listener = std::os::unix::net::UnixListener::from(fd.try_clone()?);
+        drop(fd);
+        listener.set_nonblocking(true)?;
+        return UnixListener::from_std(listener)
+    }
+
+    let path = get_socket_path(socket)?;
+    // we hold the lock, so a socket left there is stale, anything else isn't ours
+    if let Ok(metadata) = fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{:?} exists and is not a socket", path),
+            ))
+        }
+        fs::remove_file(&path)?;
+    }
+    info!("Listening on {:?}", path);
+    UnixListener::bind(path)
+}
+
+/// Writes the lines to the client until it disconnects
+async fn serve_client(mut stream: UnixStream, mut lines: mpsc::UnboundedReceiver<String>) {
+    while let Some(line) = lines.recv().await {
+        if let Err(e) = stream.write_all(line.as_bytes()).await {
+            debug!("Client disconnected: {:?}", e);
+            return
+        }
+    }
+}
+
+/// Takes the instance lock, listens for clients, and redirects the output of the mode to them:
+/// each client gets the last line on connection (unless `stateless`) then every new line.
+/// Systemd is notified once the first line is out, or right away if `stateless`.
+pub(crate) async fn start(socket: &str, stateless: bool) -> io::Result<()> {
+    let lock = lock()?;
+    let listener = get_listener(socket)?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    crate::output::redirect(sender);
+
+    if stateless {
+        sd_notify("READY=1");
+    }
+    start_watchdog();
+
+    tokio::spawn(async move {
+        // released when the daemon ends
+        let _lock = lock;
+        let mut last_line: Option<String> = None;
+        let mut clients: Vec<mpsc::UnboundedSender<String>> = vec![];
+
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => {
+                        debug!("Client connected");
+                        let (client, lines) = mpsc::unbounded_channel();
+                        if let Some(line) = last_line.as_ref().filter(|_| !stateless) {
+                            let _ = client.send(line.clone());
+                        }
+                        tokio::spawn(serve_client(stream, lines));
+                        clients.push(client);
+                    },
+                    Err(e) => warn!("Could not accept client: {:?}", e),
+                },
+                line = receiver.recv() => {
+                    let Some(line) = line else { return };
+                    if last_line.is_none() && !stateless {
+                        sd_notify("READY=1");
+                    }
+                    // a closed client only drops its own sink
+                    clients.retain(|client| client.send(line.clone()).is_ok());
+                    last_line = Some(line);
+                },
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Prints the lines of the running daemon
+pub(crate) async fn attach(socket: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let path = get_socket_path(socket)?;
+    let stream = UnixStream::connect(&path)
+        .await
+        .map_err(|e| format!("Could not connect to the daemon at {:?}: {}", path, e))?;
+
+    let mut lines = tokio::io::AsyncBufReadExt::lines(tokio::io::BufReader::new(stream));
+    while let Some(line) = lines.next_line().await? {
+        output!("{}", line);
+    }
+    Ok(())
+}
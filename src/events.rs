@@ -0,0 +1,194 @@
+use crate::instances;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::net::UnixStream;
+
+/// Names given to the comma separated values of each known event.
+/// The last name takes the rest of the line (titles may contain commas).
+const EVENT_FIELDS: [(&str, &[&str]); 38] = [
+    ("workspace", &["name"]),
+    ("workspacev2", &["id", "name"]),
+    ("focusedmon", &["monitor", "workspace"]),
+    ("activewindow", &["class", "title"]),
+    ("activewindowv2", &["address"]),
+    ("fullscreen", &["state"]),
+    ("monitorremoved", &["monitor"]),
+    ("monitoradded", &["monitor"]),
+    ("monitoraddedv2", &["id", "name", "description"]),
+    ("createworkspace", &["name"]),
+    ("createworkspacev2", &["id", "name"]),
+    ("destroyworkspace", &["name"]),
+    ("destroyworkspacev2", &["id", "name"]),
+    ("moveworkspace", &["name", "monitor"]),
+    ("moveworkspacev2", &["id", "name", "monitor"]),
+    ("renameworkspace", &["id", "name"]),
+    ("activespecial", &["name", "monitor"]),
+    ("activelayout", &["keyboard", "layout"]),
+    ("openwindow", &["address", "workspace", "class", "title"]),
+    ("closewindow", &["address"]),
+    ("movewindow", &["address", "workspace"]),
+    ("movewindowv2", &["address", "id", "name"]),
+    ("openlayer", &["namespace"]),
+    ("closelayer", &["namespace"]),
+    ("submap", &["name"]),
+    ("changefloatingmode", &["address", "floating"]),
+    ("urgent", &["address"]),
+    ("minimize", &["address", "minimized"]),
+    ("screencast", &["state", "owner"]),
+    ("windowtitle", &["address"]),
+    ("windowtitlev2", &["address", "title"]),
+    ("togglegroup", &["state", "addresses"]),
+    ("moveintogroup", &["address"]),
+    ("moveoutofgroup", &["address"]),
+    ("ignoregrouplock", &["state"]),
+    ("lockgroups", &["state"]),
+    ("pin", &["address", "pinned"]),
+    ("configreloaded", &[]),
+];
+
+/// Events with a v2 version carrying ids, Hyprland sends both
+const V2_EVENTS: [(&str, &str); 7] = [
+    ("workspace", "workspacev2"),
+    ("createworkspace", "createworkspacev2"),
+    ("destroyworkspace", "destroyworkspacev2"),
+    ("moveworkspace", "moveworkspacev2"),
+    ("movewindow", "movewindowv2"),
+    ("windowtitle", "windowtitlev2"),
+    ("monitoradded", "monitoraddedv2"),
+];
+
+/// Drops the v1 events also sent as v2, older Hyprland versions only send the v1 ones.
+/// Hyprland sends each v2 event right after its v1: until it is known
+/// whether a v2 event is sent, its v1 event is held back until the next line.
+#[derive(Default)]
+pub(crate) struct V2Filter {
+    /// v2 events known to be sent (true) or not (false)
+    known: Vec<(&'static str, bool)>,
+    held: Option<(String, serde_json::Value)>,
+}
+
+impl V2Filter {
+    fn set_known(&mut self, v2: &'static str, sent: bool) {
+        self.known.retain(|(event, _)| *event != v2);
+        self.known.push((v2, sent));
+    }
+
+    /// Returns the events to handle now that this one is received, in order
+    pub(crate) fn filter(&mut self, name: String, data: serde_json::Value) -> Vec<(String, serde_json::Value)> {
+        let mut events = vec![];
+
+        if let Some((held_name, held_data)) = self.held.take() {
+            if let Some((_, v2)) = V2_EVENTS.iter().find(|(v1, _)| *v1 == held_name) {
+                self.set_known(v2, name == *v2);
+                if name != *v2 {
+                    events.push((held_name, held_data));
+                }
+            }
+        }
+
+        if let Some((_, v2)) = V2_EVENTS.iter().find(|(_, v2)| *v2 == name) {
+            self.set_known(v2, true);
+        } else if let Some((_, v2)) = V2_EVENTS.iter().find(|(v1, _)| *v1 == name) {
+            match self.known.iter().find(|(event, _)| event == v2) {
+                Some((_, true)) => return events,
+                Some((_, false)) => {},
+                None => {
+                    self.held = Some((name, data));
+                    return events
+                },
+            }
+        }
+
+        events.push((name, data));
+        events
+    }
+}
+
+/// Returns the path of Hyprland's event socket (.socket2.sock).
+/// Newer Hyprland versions put it in $XDG_RUNTIME_DIR/hypr, older ones in /tmp/hypr.
+pub(crate) fn get_event_socket_path() -> Option<PathBuf> {
+    let signature = instances::get_signature()?;
+
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        let path = PathBuf::from(runtime_dir)
+            .join("hypr")
+            .join(&signature)
+            .join(".socket2.sock");
+        if path.exists() {
+            return Some(path)
+        }
+    }
+
+    Some(PathBuf::from("/tmp/hypr").join(&signature).join(".socket2.sock"))
+}
+
+/// Parses a line "<event>>><data>" from the event socket into
+/// the event name and a JSON object of its values.
+/// Values of unknown events are kept as a single "value" property.
+pub(crate) fn parse_event(line: &str) -> Option<(String, serde_json::Value)> {
+    let (name, payload) = line.split_once(">>")?;
+
+    let mut data = serde_json::Map::new();
+
+    match EVENT_FIELDS.iter().find(|(event, _)| *event == name) {
+        Some((_, fields)) => {
+            if !fields.is_empty() {
+                for (field, value) in fields.iter().zip(payload.splitn(fields.len(), ',')) {
+                    data.insert(String::from(*field), serde_json::Value::String(String::from(value)));
+                }
+            }
+        }
+        None => {
+            data.insert(String::from("value"), serde_json::Value::String(String::from(payload)));
+        }
+    }
+
+    Some((String::from(name), serde_json::Value::Object(data)))
+}
+
+/// Connects to the event socket, returns the lines received.
+pub(crate) async fn connect_events() -> io::Result<Lines<BufReader<UnixStream>>> {
+    let socket_path = get_event_socket_path().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "HYPRLAND_INSTANCE_SIGNATURE is not set. Is hyprland running?")
+    })?;
+
+    let stream = UnixStream::connect(socket_path).await?;
+
+    Ok(BufReader::new(stream).lines())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the names of the events handled, in order
+    fn filter_lines(lines: &[&str]) -> Vec<String> {
+        let mut filter = V2Filter::default();
+        lines
+            .iter()
+            .flat_map(|line| {
+                let (name, data) = parse_event(line).unwrap();
+                filter.filter(name, data)
+            })
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn v1_events_are_dropped_from_the_first_pair() {
+        assert_eq!(
+            filter_lines(&["movewindow>>a,2", "movewindowv2>>a,2,2", "workspace>>2", "workspacev2>>2,2", "workspace>>3"]),
+            vec!["movewindowv2", "workspacev2"],
+        );
+    }
+
+    #[test]
+    fn v1_events_are_kept_without_v2() {
+        assert_eq!(
+            filter_lines(&["movewindow>>a,2", "activewindow>>kitty,a", "movewindow>>a,3", "closewindow>>a"]),
+            vec!["movewindow", "activewindow", "movewindow", "closewindow"],
+        );
+    }
+}
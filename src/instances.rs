@@ -0,0 +1,117 @@
+use std::env;
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Seconds between two looks for a newer instance when following
+pub(crate) const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Signature of the selected instance, HYPRLAND_INSTANCE_SIGNATURE until one is selected.
+/// Kept here as the environment can't be changed safely while tasks read it.
+static SIGNATURE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Listens to (and queries) this instance from now on
+pub(crate) fn select(signature: &str) {
+    SIGNATURE.lock().unwrap().replace(String::from(signature));
+}
+
+pub(crate) fn get_signature() -> Option<String> {
+    SIGNATURE
+        .lock()
+        .unwrap()
+        .clone()
+        .or_else(|| env::var("HYPRLAND_INSTANCE_SIGNATURE").ok())
+}
+
+/// A running Hyprland instance, from its directory in $XDG_RUNTIME_DIR/hypr
+pub(crate) struct RunningInstance {
+    pub(crate) signature: String,
+    /// from "hyprland.lock", older versions don't write it
+    pub(crate) pid: Option<u32>,
+    pub(crate) wayland_display: Option<String>,
+    /// seconds since epoch the event socket was created
+    pub(crate) time: u64,
+}
+
+/// Newer Hyprland versions put the instances in $XDG_RUNTIME_DIR/hypr, older ones in /tmp/hypr.
+fn get_instances_dir() -> PathBuf {
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        let dir = PathBuf::from(runtime_dir).join("hypr");
+        if dir.is_dir() {
+            return dir
+        }
+    }
+    PathBuf::from("/tmp/hypr")
+}
+
+/// Reads an instance directory, `None` if Hyprland is not running there anymore
+fn read_instance(dir: &Path) -> Option<RunningInstance> {
+    let signature = dir.file_name()?.to_str()?.to_string();
+    let socket_path = dir.join(".socket2.sock");
+    let time = fs::metadata(&socket_path)
+        .and_then(|m| m.modified())
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    // "hyprland.lock" has the pid then the wayland display, one per line
+    let lock = fs::read_to_string(dir.join("hyprland.lock")).unwrap_or_default();
+    let mut lines = lock.lines();
+    let pid = lines.next().and_then(|v| v.trim().parse::<u32>().ok());
+    let wayland_display = lines.next().map(|v| String::from(v.trim())).filter(|v| !v.is_empty());
+
+    let running = match pid {
+        Some(pid) => Path::new("/proc").join(pid.to_string()).exists(),
+        // the socket file stays after a crash, only a live instance accepts
+        None => UnixStream::connect(&socket_path).is_ok(),
+    };
+    if !running {
+        return None
+    }
+
+    Some(RunningInstance { signature, pid, wayland_display, time })
+}
+
+/// Returns the running instances, the newest first
+pub(crate) fn get_instances() -> Vec<RunningInstance> {
+    let Ok(entries) = fs::read_dir(get_instances_dir()) else { return vec![] };
+
+    let mut instances: Vec<RunningInstance> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| read_instance(&entry.path()))
+        .collect();
+    instances.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| b.signature.cmp(&a.signature)));
+    instances
+}
+
+pub(crate) fn get_newest_signature() -> Option<String> {
+    get_instances().into_iter().next().map(|i| i.signature)
+}
+
+pub(crate) fn is_running(signature: &str) -> bool {
+    read_instance(&get_instances_dir().join(signature)).is_some()
+}
+
+/// Waits until an instance runs, returns the newest one
+pub(crate) async fn wait_newest() -> String {
+    loop {
+        if let Some(signature) = get_newest_signature() {
+            return signature
+        }
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+    }
+}
+
+/// Waits until the newest instance is not the one given
+pub(crate) async fn wait_other_newest(signature: &str) {
+    loop {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+        if get_newest_signature().is_some_and(|newest| newest != signature) {
+            return
+        }
+    }
+}
@@ -1,23 +1,184 @@
+#[macro_use]
+pub mod output;
+
+pub mod logging;
+
+mod cache;
+mod daemon;
+mod events;
+mod instances;
+mod notify;
+mod router;
+mod session;
+
 use hyprland::data::Workspace;
-use hyprland::event_listener::EventListenerMutable as EventListener;
-use hyprland::prelude::*;
-use serde_json;
+use log::{debug, error, info, warn, LevelFilter};
+use logging::{LogFormat, LogSink};
+use router::{run_stream, Refresh, Stream, Trigger};
+use std::collections::HashMap;
 use std::error::Error;
-use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+use tokio::signal::unix::{signal, SignalKind};
 
 const EWW_CMD: &str = "eww";
 
+/// Default seconds between two full resyncs of the cache
+const DEFAULT_RESYNC_SECS: u64 = 60;
+
+#[derive(Clone)]
 pub enum Event {
     Workspace,
     ActiveWorkspace,
     Submap,
+    Monitors,
+    Fullscreen,
+    History,
+    Windows,
+    Raw,
+    Record,
+    Replay(Box<Event>),
+    Daemon(Box<Event>),
+    Client,
+    Instances,
+    Check,
     Invalid
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sort {
+    Id,
+    Name,
+    /// by monitor, then id
+    Monitor,
+    Mru
+}
+
+/// How the workspace list is sorted and filtered
+#[derive(Clone, Default)]
+pub struct View {
+    /// `None` keeps the order of the persistent list (id order without one)
+    pub sort: Option<Sort>,
+    /// hide unfocused workspaces without windows
+    pub hide_empty: bool,
+    pub hide_special: bool,
+    /// only show the workspaces on this monitor ("focused" for the focused one)
+    pub monitor: Option<String>,
+    /// show at most this many workspaces
+    pub limit: Option<usize>
+}
+
+/// What to do with the live workspaces which are not persistent
+#[derive(Clone, Copy, PartialEq)]
+pub enum Merge {
+    /// dropped
+    None,
+    /// listed after the persistent ones
+    Append,
+    /// inserted among the persistent ones by id
+    Sorted
+}
+
+/// Hyprland instance to listen to
+#[derive(Clone, PartialEq)]
+pub enum Instance {
+    /// HYPRLAND_INSTANCE_SIGNATURE
+    Current,
+    Signature(String),
+    Newest,
+    /// the newest instance, switching when a newer one starts
+    Follow
+}
+
+/// Persistent workspaces "<first>-<last>[@<monitor>]"
+#[derive(Clone)]
+pub struct PersistentRange {
+    pub first: i64,
+    pub last: i64,
+    pub monitor: Option<String>
+}
+
+fn parse_persistent_range(v: &str) -> Option<PersistentRange> {
+    let (range, monitor) = match v.split_once('@') {
+        Some((range, monitor)) => (range, Some(String::from(monitor))),
+        None => (v, None),
+    };
+    let (first, last) = match range.split_once('-') {
+        Some((first, last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+        None => {
+            let id = range.trim().parse().ok()?;
+            (id, id)
+        }
+    };
+    if first > last {
+        return None
+    }
+    Some(PersistentRange { first, last, monitor })
+}
+
+#[derive(Clone)]
 pub struct Config {
     pub event: Event,
-    pub ewwvar: String
+    pub ewwvar: String,
+    pub binds: bool,
+    pub view: View,
+    pub once: bool,
+    pub only: Vec<String>,
+    pub exclude: Vec<String>,
+    pub file: String,
+    pub cache: bool,
+    pub resync: u64,
+    pub persistent: Vec<PersistentRange>,
+    /// persistent workspaces from Hyprland's workspace rules
+    pub persistent_hyprland: bool,
+    pub name_pattern: String,
+    pub icon_pattern: String,
+    /// `None` for the default of the source (none for --eww, append for --persistent)
+    pub merge: Option<Merge>,
+    /// path of the label/icon rules
+    pub rules: String,
+    /// send desktop notifications on submap changes and urgent windows
+    pub notify: bool,
+    /// active workspace as a JSON object instead of its id
+    pub json: bool,
+    pub log_level: LevelFilter,
+    pub log_format: LogFormat,
+    pub log_sink: LogSink,
+    /// client socket of the daemon, in the runtime directory if empty
+    pub socket: String,
+    pub instance: Instance
+}
+
+fn parse_event(v: &str) -> Event {
+    if v == "workspace" || v == "workspaces" {
+        Event::Workspace
+    } else if v == "active-workspace" {
+        Event::ActiveWorkspace
+    } else if v == "submap" {
+        Event::Submap
+    } else if v == "monitor" || v == "monitors" {
+        Event::Monitors
+    } else if v == "fullscreen" {
+        Event::Fullscreen
+    } else if v == "history" {
+        Event::History
+    } else if v == "window" || v == "windows" {
+        Event::Windows
+    } else if v == "raw" {
+        Event::Raw
+    } else if v == "record" {
+        Event::Record
+    } else if v == "check" {
+        Event::Check
+    } else if v == "client" {
+        Event::Client
+    } else if v == "instances" {
+        Event::Instances
+    } else {
+        Event::Invalid
+    }
 }
 
 impl Config {
@@ -42,103 +203,519 @@ impl Config {
         
         let event = match extracted_args_iter.next() {
             Some(v) => {
-                if v == "workspace" || v == "workspaces" {
-                    Event::Workspace
-                } else if v == "active-workspace" {
-                    Event::ActiveWorkspace
-                } else if v == "submap" {
-                    Event::Submap
+                if v == "replay" {
+                    // replay the recording through the mode given as next argument
+                    let replayed = match extracted_args_iter.next() {
+                        Some(mode) => parse_event(&mode),
+                        None => Event::Workspace,
+                    };
+                    Event::Replay(Box::new(replayed))
+                } else if v == "daemon" {
+                    // serve the mode given as next argument to the clients
+                    let served = match extracted_args_iter.next() {
+                        Some(mode) => parse_event(&mode),
+                        None => Event::Workspace,
+                    };
+                    Event::Daemon(Box::new(served))
                 } else {
-                    Event::Invalid
+                    parse_event(&v)
                 }
             },
             None => Event::Workspace,
         };
 
         let mut ewwvar = String::new();
+        let mut binds = false;
+        let mut view = View::default();
+        let mut once = false;
+        let mut only: Vec<String> = vec![];
+        let mut exclude: Vec<String> = vec![];
+        let mut file = String::new();
+        let mut cache = false;
+        let mut resync = DEFAULT_RESYNC_SECS;
+        let mut persistent: Vec<PersistentRange> = vec![];
+        let mut persistent_hyprland = false;
+        let mut name_pattern = String::new();
+        let mut icon_pattern = String::new();
+        let mut merge: Option<Merge> = None;
+        let mut rules = String::new();
+        let mut notify = false;
+        let mut json = false;
+        let mut verbose = 0;
+        let mut quiet = false;
+        let mut log_format = LogFormat::Text;
+        let mut log_sink = LogSink::Stderr;
+        let mut socket = String::new();
+        let mut instance = Instance::Current;
 
         for arg in options_iter {
-            if arg.starts_with("--eww=") {
-                ewwvar = String::from(&arg[6..]);
+            if let Some(value) = arg.strip_prefix("--eww=") {
+                ewwvar = String::from(value);
+            } else if arg == "--binds" {
+                binds = true;
+            } else if let Some(value) = arg.strip_prefix("--sort=") {
+                view.sort = match value {
+                    "id" => Some(Sort::Id),
+                    "name" => Some(Sort::Name),
+                    "monitor" => Some(Sort::Monitor),
+                    "mru" => Some(Sort::Mru),
+                    _ => return Err("Invalid value for --sort (expected id, name, monitor or mru)")
+                };
+            } else if arg == "--hide-empty" {
+                view.hide_empty = true;
+            } else if arg == "--hide-special" {
+                view.hide_special = true;
+            } else if let Some(value) = arg.strip_prefix("--monitor=") {
+                view.monitor = Some(String::from(value));
+            } else if let Some(value) = arg.strip_prefix("--limit=") {
+                view.limit = match value.parse() {
+                    Ok(limit) => Some(limit),
+                    Err(_) => return Err("Invalid value for --limit (expected a number)")
+                };
+            } else if arg == "--once" {
+                once = true;
+            } else if let Some(value) = arg.strip_prefix("--only=") {
+                only.extend(value.split(',').map(String::from));
+            } else if let Some(value) = arg.strip_prefix("--exclude=") {
+                exclude.extend(value.split(',').map(String::from));
+            } else if let Some(value) = arg.strip_prefix("--file=") {
+                file = String::from(value);
+            } else if let Some(value) = arg.strip_prefix("--socket=") {
+                socket = String::from(value);
+            } else if let Some(value) = arg.strip_prefix("--instance=") {
+                instance = match value {
+                    "" => return Err("Missing value for --instance (expected a signature, newest or follow)"),
+                    "newest" => Instance::Newest,
+                    "follow" => Instance::Follow,
+                    signature => Instance::Signature(String::from(signature))
+                };
+            } else if arg == "--cache" {
+                cache = true;
+            } else if let Some(value) = arg.strip_prefix("--resync=") {
+                resync = match value.parse() {
+                    Ok(secs) if secs > 0 => secs,
+                    _ => return Err("Invalid value for --resync (expected seconds > 0)")
+                };
+            } else if arg == "--persistent=hyprland" {
+                persistent_hyprland = true;
+            } else if let Some(value) = arg.strip_prefix("--persistent=") {
+                for range in value.split(',') {
+                    match parse_persistent_range(range) {
+                        Some(range) => persistent.push(range),
+                        None => return Err("Invalid value for --persistent (expected <first>-<last>[@<monitor>],...)")
+                    }
+                }
+            } else if let Some(value) = arg.strip_prefix("--name=") {
+                name_pattern = String::from(value);
+            } else if let Some(value) = arg.strip_prefix("--icon=") {
+                icon_pattern = String::from(value);
+            } else if let Some(value) = arg.strip_prefix("--merge=") {
+                merge = match value {
+                    "none" => Some(Merge::None),
+                    "append" => Some(Merge::Append),
+                    "sorted" => Some(Merge::Sorted),
+                    _ => return Err("Invalid value for --merge (expected none, append or sorted)")
+                };
+            } else if let Some(value) = arg.strip_prefix("--rules=") {
+                rules = String::from(value);
+            } else if arg == "--notify" {
+                notify = true;
+            } else if arg == "--json" {
+                json = true;
+            } else if arg == "--verbose" {
+                verbose += 1;
+            } else if arg == "--quiet" {
+                quiet = true;
+            } else if let Some(value) = arg.strip_prefix("--log-format=") {
+                log_format = match value {
+                    "text" => LogFormat::Text,
+                    "json" => LogFormat::Json,
+                    _ => return Err("Invalid value for --log-format (expected text or json)")
+                };
+            } else if let Some(value) = arg.strip_prefix("--log=") {
+                log_sink = match value {
+                    "stderr" => LogSink::Stderr,
+                    "journald" => LogSink::Journald,
+                    _ => return Err("Invalid value for --log (expected stderr or journald)")
+                };
             }
         }
 
+        let sources = [!ewwvar.is_empty(), !persistent.is_empty(), persistent_hyprland];
+        if sources.iter().filter(|source| **source).count() > 1 {
+            return Err("Use only one of --eww, --persistent=<ranges> or --persistent=hyprland")
+        }
+
+        // persistent workspaces have their own names and icons, check validates both files
+        if !rules.is_empty() && sources.contains(&true) && !matches!(event, Event::Check) {
+            return Err("Use --rules without --eww and --persistent")
+        }
+
+        // --quiet/--verbose, then the environment, then info
+        let log_level = if quiet {
+            LevelFilter::Error
+        } else if verbose > 1 {
+            LevelFilter::Trace
+        } else if verbose == 1 {
+            LevelFilter::Debug
+        } else {
+            logging::get_env_level()?.unwrap_or(LevelFilter::Info)
+        };
+
         Ok(Config {
             event,
-            ewwvar
+            ewwvar,
+            binds,
+            view,
+            once,
+            only,
+            exclude,
+            file,
+            cache,
+            resync,
+            persistent,
+            persistent_hyprland,
+            name_pattern,
+            icon_pattern,
+            merge,
+            rules,
+            notify,
+            json,
+            log_level,
+            log_format,
+            log_sink,
+            socket,
+            instance
         })
     }
 }
 
-fn exec_hyprctl_command(hyprctl_command: &str) -> serde_json::Value {
-    let output = Command::new("hyprctl")
-        .arg("-j")
-        .arg(hyprctl_command)
-        .output()
-        .unwrap();
+async fn exec_hyprctl_command(hyprctl_command: &str) -> serde_json::Value {
+    if let Some(value) = session::replayed_query(hyprctl_command) {
+        return value
+    }
+
+    if cache::needs_resync(hyprctl_command) {
+        resync_cache().await;
+    }
+    if let Some(value) = cache::query(hyprctl_command) {
+        return value
+    }
+
+    run_hyprctl_command(hyprctl_command).await
+}
+
+/// Returns "hyprctl" for the selected Hyprland instance
+fn hyprctl() -> Command {
+    let mut command = Command::new("hyprctl");
+    if let Some(signature) = instances::get_signature() {
+        command.env("HYPRLAND_INSTANCE_SIGNATURE", signature);
+    }
+    command
+}
+
+/// Runs "hyprctl -j <command>", bypassing the cache.
+/// Returns `Null` if it fails (e.g. the instance stopped and hyprctl printed an error).
+async fn run_hyprctl_command(hyprctl_command: &str) -> serde_json::Value {
+    let output = match hyprctl().arg("-j").arg(hyprctl_command).output().await {
+        Ok(output) => output,
+        Err(e) => {
+            error!("Could not execute command: hyprctl -j {}: {:?}", hyprctl_command, e);
+            return serde_json::Value::Null
+        }
+    };
+
+    let output_string = String::from_utf8_lossy(&output.stdout);
+
+    match serde_json::from_str(&output_string) {
+        Ok(value) => {
+            session::record_query(hyprctl_command, &value);
+            value
+        }
+        Err(_) => {
+            error!("hyprctl -j {} did not return JSON: {:?}", hyprctl_command, output_string.trim());
+            serde_json::Value::Null
+        }
+    }
+}
+
+/// Seeds the cache again from hyprctl
+async fn resync_cache() {
+    debug!("Resyncing cache");
+    cache::begin_resync();
+    let workspaces = run_hyprctl_command("workspaces").await;
+    let monitors = run_hyprctl_command("monitors").await;
+    let clients = run_hyprctl_command("clients").await;
+    let active_window = run_hyprctl_command("activewindow").await;
+    cache::resync(workspaces, monitors, clients, active_window);
+}
+
+/// Serves the queries from a state updated by the events,
+/// fully resynced every `resync` seconds to correct any drift.
+fn start_cache(resync: u64) {
+    cache::enable();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(resync));
+        // the first tick is immediate, the first query seeds the cache anyway
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            cache::mark_dirty();
+        }
+    });
+}
 
-    let output_string = String::from_utf8(output.stdout).unwrap();
+/// Same as `Workspace::get_active()` but through "hyprctl -j activeworkspace"
+/// so it can be recorded and replayed.
+async fn get_active_workspace() -> hyprland::Result<Workspace> {
+    Ok(serde_json::from_value(exec_hyprctl_command("activeworkspace").await)?)
+}
 
-    serde_json::from_str(output_string.as_str()).unwrap()
+/// Loads the rules giving a label and an icon to each workspace, a JSON object:
+/// { "labels": { "<id>": "<label>" }, "icons": { "<id>": "<icon>" },
+///   "classes": { "<window class>": "<icon>" }, "default_icon": "<icon>" }
+fn load_workspace_rules(path: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+    let rules: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    if !rules.is_object() {
+        return Err(format!("Rules {:?} are not a JSON object", path).into())
+    }
+    for key in ["labels", "icons", "classes"] {
+        match rules.get(key) {
+            None => {},
+            Some(serde_json::Value::Object(map)) => {
+                if let Some((k, v)) = map.iter().find(|(_, v)| !v.is_string()) {
+                    return Err(format!("Rules {:?}: \"{}\".\"{}\" is not a string but {}", path, key, k, v).into())
+                }
+            },
+            Some(other) => return Err(format!("Rules {:?}: \"{}\" is not a JSON object but {}", path, key, other).into()),
+        }
+    }
+    if let Some(icon) = rules.get("default_icon").filter(|v| !v.is_string()) {
+        return Err(format!("Rules {:?}: \"default_icon\" is not a string but {}", path, icon).into())
+    }
+    Ok(rules)
 }
 
-fn get_workspaces_list(active_id: i64) -> serde_json::Value {
+/// Returns the class of most windows on each workspace (first one on a tie)
+async fn get_dominant_classes() -> HashMap<i64, String> {
+    let clients = exec_hyprctl_command("clients").await;
+
+    let mut counts: HashMap<i64, Vec<(String, usize)>> = HashMap::new();
+    if let serde_json::Value::Array(clients_array) = clients {
+        for client in clients_array {
+            let id = client["workspace"]["id"].as_i64();
+            let class = client.get("class").and_then(|v| v.as_str()).unwrap_or("");
+            if let (Some(id), false) = (id, class.is_empty()) {
+                let classes = counts.entry(id).or_default();
+                match classes.iter_mut().find(|(c, _)| c == class) {
+                    Some((_, count)) => *count += 1,
+                    None => classes.push((String::from(class), 1)),
+                }
+            }
+        }
+    }
+
+    counts
+        .into_iter()
+        .filter_map(|(id, classes)| {
+            let mut dominant: Option<(String, usize)> = None;
+            for (class, count) in classes {
+                if dominant.as_ref().is_none_or(|(_, max)| count > *max) {
+                    dominant = Some((class, count));
+                }
+            }
+            dominant.map(|(class, _)| (id, class))
+        })
+        .collect()
+}
+
+/// Adds "label" (static per id, or the Hyprland name) and
+/// "icon" (static per id, from the dominant window class, or the default) to the workspace.
+fn apply_workspace_rules(work: &mut serde_json::Map<String, serde_json::Value>, rules: &serde_json::Value, classes: &HashMap<i64, String>) {
+    let id = work.get("id").and_then(|v| v.as_i64()).unwrap_or(0);
+    let key = id.to_string();
+
+    let label = rules["labels"][&key]
+        .as_str()
+        .or_else(|| work.get("name").and_then(|v| v.as_str()))
+        .map(String::from)
+        .unwrap_or(key.clone());
+
+    let icon = rules["icons"][&key]
+        .as_str()
+        .or_else(|| classes.get(&id).and_then(|class| rules["classes"][class].as_str()))
+        .or_else(|| rules["default_icon"].as_str())
+        .unwrap_or("");
+
+    let icon = String::from(icon);
+    work.insert(String::from("label"), serde_json::Value::String(label));
+    work.insert(String::from("icon"), serde_json::Value::String(icon));
+}
+
+/// Adds property "occupied": true if the workspace has windows
+fn insert_occupied(work: &mut serde_json::Map<String, serde_json::Value>) {
+    let windows = work.get("windows").and_then(|v| v.as_i64()).unwrap_or(0);
+    work.insert(String::from("occupied"), serde_json::Value::Bool(windows > 0));
+}
+
+fn get_work_id(work: &serde_json::Value) -> i64 {
+    work.get("id")
+        .and_then(|v| v.as_i64())
+        .or_else(|| work.get("num").and_then(|v| v.as_i64()))
+        .unwrap_or(0)
+}
+
+fn get_work_str<'a>(work: &'a serde_json::Value, key: &str) -> &'a str {
+    work.get(key).and_then(|v| v.as_str()).unwrap_or("")
+}
+
+/// Sorts the workspaces, MRU keeps the id order for never focused ones
+fn sort_workspaces(workspaces: &mut [serde_json::Value], sort: Sort, history: &[i32]) {
+    workspaces.sort_by_key(get_work_id);
+
+    match sort {
+        Sort::Id => {},
+        Sort::Name => workspaces.sort_by(|a, b| get_work_str(a, "name").cmp(get_work_str(b, "name"))),
+        Sort::Monitor => workspaces.sort_by(|a, b| get_work_str(a, "monitor").cmp(get_work_str(b, "monitor"))),
+        Sort::Mru => workspaces.sort_by_key(|work| {
+            let id = get_work_id(work);
+            history
+                .iter()
+                .position(|h| i64::from(*h) == id)
+                .unwrap_or(usize::MAX)
+        }),
+    }
+}
+
+/// Removes the workspaces hidden by the view, then the ones over the limit
+fn filter_workspaces(workspaces: &mut Vec<serde_json::Value>, view: &View, active_id: i64) {
+    let monitor = match view.monitor.as_deref() {
+        Some("focused") => workspaces
+            .iter()
+            .find(|w| get_work_id(w) == active_id)
+            .map(|w| String::from(get_work_str(w, "monitor"))),
+        other => other.map(String::from),
+    };
+
+    workspaces.retain(|work| {
+        let id = get_work_id(work);
+        let windows = work.get("windows").and_then(|v| v.as_i64()).unwrap_or(0);
+        if view.hide_empty && windows == 0 && id != active_id {
+            return false
+        }
+        if view.hide_special && get_work_str(work, "name").starts_with("special") {
+            return false
+        }
+        match &monitor {
+            Some(monitor) => get_work_str(work, "monitor") == monitor,
+            None => true,
+        }
+    });
+
+    if let Some(limit) = view.limit {
+        workspaces.truncate(limit);
+    }
+}
+
+async fn get_workspaces_list(active_id: i64, view: &View, history: &[i32], rules: Option<&serde_json::Value>) -> serde_json::Value {
     // get list of workspaces
-    let mut object = exec_hyprctl_command("workspaces");
+    let mut object = exec_hyprctl_command("workspaces").await;
     let workspaces = object.as_array_mut();
 
+    // only query the windows if an icon depends on them
+    let classes = match rules {
+        Some(rules) if rules.get("classes").is_some() => get_dominant_classes().await,
+        _ => HashMap::new(),
+    };
+
     if let Some(workspaces_array) = workspaces {
 
         // sort workspaces
-        workspaces_array.sort_by(|a, b| {
-            let a_id = a.get("id").unwrap().as_i64().unwrap();
-            let b_id = b.get("id").unwrap().as_i64().unwrap();
-            a_id.partial_cmp(&b_id).unwrap()
-        });
+        sort_workspaces(workspaces_array, view.sort.unwrap_or(Sort::Id), history);
 
         // loop through list
-        for work in workspaces_array {
+        for work in workspaces_array.iter_mut() {
             // get workspace
             let work_json = work.as_object_mut();
             if let Some(ev) = work_json {
                 // get workspace id
                 let value_option = ev.get_mut("id");
-                if let Some(value) = value_option {
-                    if let serde_json::Value::Number(num) = value {
-                        if let Some(num_value) = num.as_i64() {
-                            // add property "num" with same value from property "id"
-                            ev.insert(
-                                String::from("num"),
-                                serde_json::to_value(num_value).unwrap(),
-                            );
-
-                            // add property "focused": true if "id" equals the function's argument
-                            let mut focused = false;
-                            if num_value == active_id {
-                                focused = true;
-                            }
-                            ev.insert(String::from("focused"), serde_json::Value::Bool(focused));
+                if let Some(serde_json::Value::Number(num)) = value_option {
+                    if let Some(num_value) = num.as_i64() {
+                        // add property "num" with same value from property "id"
+                        ev.insert(
+                            String::from("num"),
+                            serde_json::to_value(num_value).unwrap(),
+                        );
+
+                        // add property "focused": true if "id" equals the function's argument
+                        let mut focused = false;
+                        if num_value == active_id {
+                            focused = true;
                         }
+                        ev.insert(String::from("focused"), serde_json::Value::Bool(focused));
                     }
                 }
+
+                insert_occupied(ev);
+
+                if let Some(rules) = rules {
+                    apply_workspace_rules(ev, rules, &classes);
+                }
             }
         }
+
+        filter_workspaces(workspaces_array, view, active_id);
     } else {
-        eprintln!("error - workspace list is not a JSON array!");
+        error!("workspace list is not a JSON array!");
     }
 
     object
 }
 
-fn get_number(option_value: Option<&mut serde_json::Value>) -> Option<i64> {
-    if let Some(value) = option_value {
-        if let serde_json::Value::Number(num) = value {
-            if let Some(num_value) = num.as_i64() {
-                return Some(num_value)
-            }
+/// How a persistent workspace is matched with the live ones
+enum WorkspaceKey {
+    Id(i64),
+    Name(String),
+}
+
+impl WorkspaceKey {
+    fn matches(&self, work: &serde_json::Value) -> bool {
+        match self {
+            WorkspaceKey::Id(id) => work.get("id").and_then(|v| v.as_i64()) == Some(*id),
+            WorkspaceKey::Name(name) => work.get("name").and_then(|v| v.as_str()) == Some(name.as_str()),
         }
     }
-    None
+}
+
+/// Returns the key of a persistent workspace, or why it is invalid.
+/// Its "id" is either a workspace id (number or numeric string) or a workspace name
+/// ("code", "name:code" or "special:scratch").
+fn get_persistent_key(work: &serde_json::Value) -> Result<WorkspaceKey, String> {
+    let Some(work_map) = work.as_object() else {
+        return Err(String::from("not a JSON object"))
+    };
+
+    match work_map.get("id") {
+        Some(serde_json::Value::Number(num)) => match num.as_i64() {
+            Some(id) => Ok(WorkspaceKey::Id(id)),
+            None => Err(format!("\"id\" {} is not an integer", num)),
+        },
+        Some(serde_json::Value::String(value)) => {
+            let value = value.trim();
+            if value.is_empty() {
+                Err(String::from("\"id\" is empty"))
+            } else if let Ok(id) = value.parse() {
+                Ok(WorkspaceKey::Id(id))
+            } else {
+                // Hyprland names "name:code" workspaces "code"
+                let name = value.strip_prefix("name:").unwrap_or(value);
+                Ok(WorkspaceKey::Name(String::from(name)))
+            }
+        },
+        Some(other) => Err(format!("\"id\" {} is neither a number nor a name", other)),
+        None => Err(String::from("missing \"id\"")),
+    }
 }
 
 fn assign_map<'a>(target: &'a mut serde_json::Map<String, serde_json::Value>, source: &serde_json::Map<String, serde_json::Value>) -> &'a mut serde_json::Map<String, serde_json::Value>{
@@ -148,9 +725,12 @@ fn assign_map<'a>(target: &'a mut serde_json::Map<String, serde_json::Value>, so
     target
 }
 
-fn get_persistent_workspaces_list(persistent_workspaces: Vec<serde_json::Value>, active_id: i64) -> serde_json::Value {
+/// Merges the persistent workspaces with the live ones, in the order of the persistent list.
+/// The live workspaces not in the persistent list are added as `merge` says,
+/// with "persistent": false. The view's sort, if any, replaces the order of the persistent list.
+async fn get_persistent_workspaces_list(persistent_workspaces: Vec<serde_json::Value>, active_id: i64, merge: Merge, view: &View, history: &[i32]) -> serde_json::Value {
     // get list of workspaces
-    let mut object = exec_hyprctl_command("workspaces");
+    let mut object = exec_hyprctl_command("workspaces").await;
     let workspaces = object.as_array_mut();
 
     let mut result: Vec<serde_json::Value> = vec![];
@@ -158,61 +738,103 @@ fn get_persistent_workspaces_list(persistent_workspaces: Vec<serde_json::Value>,
     if let Some(workspaces_array) = workspaces {
 
         // sort workspaces
-        workspaces_array.sort_by(|a, b| {
-            let a_id = a.get("id").unwrap().as_i64().unwrap();
-            let b_id = b.get("id").unwrap().as_i64().unwrap();
-            a_id.partial_cmp(&b_id).unwrap()
-        });
+        workspaces_array.sort_by_key(get_work_id);
 
         // iteration/loop through persistent list
-        for mut persistent_work in persistent_workspaces {
-            let persistent_work_json = persistent_work.as_object_mut();
-            if let Some(pwork_map) = persistent_work_json {
-                let pid_option = get_number(pwork_map.get_mut("id"));
+        for (index, mut persistent_work) in persistent_workspaces.into_iter().enumerate() {
+            let key = match get_persistent_key(&persistent_work) {
+                Ok(key) => key,
+                Err(reason) => {
+                    warn!("persistent workspace #{} {} is invalid: {}", index + 1, persistent_work, reason);
+                    continue;
+                }
+            };
+
+            if let Some(pwork_map) = persistent_work.as_object_mut() {
                 let mut map_to_insert = pwork_map;
-                let mut is_active = false;
-                if let Some(pid) = pid_option {
-                    
-                    // fresh borrow "workspaces_array" to use inside of iteration loop
-                    for work in &mut *workspaces_array {
-                        // get workspace
-                        let work_json = work.as_object_mut();
-                        if let Some(work_map) = work_json {
-                            // get workspace id
-                            let id_option = get_number(work_map.get_mut("id"));
-                            if let Some(id) = id_option {
-                                if pid == id {
-                                    // clone, mix and break
-                                    map_to_insert = assign_map(work_map, map_to_insert);
-                                    is_active = true;
-                                    break;
-                                }
-                            }
+                let mut live_id: Option<i64> = None;
+
+                // fresh borrow "workspaces_array" to use inside of iteration loop
+                for work in &mut *workspaces_array {
+                    if key.matches(work) {
+                        live_id = work.get("id").and_then(|v| v.as_i64());
+                        if let Some(work_map) = work.as_object_mut() {
+                            // the declared monitor only places the workspaces which aren't live,
+                            // a live one is shown on the monitor it is on
+                            let mut declared = map_to_insert.clone();
+                            declared.remove("monitor");
+                            // clone, mix and break
+                            map_to_insert = assign_map(work_map, &declared);
                         }
+                        break;
                     }
+                }
 
-                    map_to_insert.insert(
-                        String::from("num"),
-                        serde_json::to_value(pid).unwrap(),
-                    );
+                // keep the id of the live workspace over a name key
+                if let Some(id) = live_id {
+                    map_to_insert.insert(String::from("id"), serde_json::to_value(id).unwrap());
+                }
 
-                    map_to_insert.insert(String::from("active"), serde_json::Value::Bool(is_active));
+                // a workspace which isn't live is named after its key, as Hyprland would
+                if live_id.is_none() && !map_to_insert.contains_key("name") {
+                    let name = match &key {
+                        WorkspaceKey::Id(id) => id.to_string(),
+                        WorkspaceKey::Name(name) => name.clone(),
+                    };
+                    map_to_insert.insert(String::from("name"), serde_json::Value::String(name));
+                }
 
-                    // add property "focused": true if "id" equals the function's argument
-                    let mut focused = false;
-                    if pid == active_id {
-                        focused = true;
-                    }
-                    map_to_insert.insert(String::from("focused"), serde_json::Value::Bool(focused));
+                // null for a named workspace which doesn't exist
+                let num = live_id.or(match key {
+                    WorkspaceKey::Id(id) => Some(id),
+                    WorkspaceKey::Name(_) => None,
+                });
+                map_to_insert.insert(String::from("num"), serde_json::to_value(num).unwrap());
+
+                map_to_insert.insert(String::from("active"), serde_json::Value::Bool(live_id.is_some()));
+                map_to_insert.insert(String::from("persistent"), serde_json::Value::Bool(true));
+
+                // add property "focused": true if "num" equals the function's argument
+                let focused = num == Some(active_id);
+                map_to_insert.insert(String::from("focused"), serde_json::Value::Bool(focused));
 
-                    result.push(serde_json::to_value(map_to_insert).unwrap());
+                insert_occupied(map_to_insert);
+
+                result.push(serde_json::to_value(map_to_insert).unwrap());
+            }
+        }
+
+        if merge != Merge::None {
+            for work in workspaces_array.iter() {
+                let id = work.get("id").and_then(|v| v.as_i64());
+                let listed = result.iter().any(|r| r.get("num").and_then(|v| v.as_i64()) == id);
+                if let (Some(id), false) = (id, listed) {
+                    let mut map_to_insert = work.as_object().cloned().unwrap_or_default();
+                    map_to_insert.insert(String::from("num"), serde_json::to_value(id).unwrap());
+                    map_to_insert.insert(String::from("active"), serde_json::Value::Bool(true));
+                    map_to_insert.insert(String::from("focused"), serde_json::Value::Bool(id == active_id));
+                    map_to_insert.insert(String::from("persistent"), serde_json::Value::Bool(false));
+                    insert_occupied(&mut map_to_insert);
+
+                    // before the first entry with a greater id, at the end otherwise
+                    let position = match merge {
+                        Merge::Sorted => result
+                            .iter()
+                            .position(|r| r.get("num").and_then(|v| v.as_i64()).is_some_and(|num| num > id))
+                            .unwrap_or(result.len()),
+                        _ => result.len(),
+                    };
+                    result.insert(position, serde_json::Value::Object(map_to_insert));
                 }
             }
+        }
 
-            //result.push(persistent_work);
+        if let Some(sort) = view.sort {
+            sort_workspaces(&mut result, sort, history);
         }
+        filter_workspaces(&mut result, view, active_id);
     } else {
-        eprintln!("error - workspace list is not a JSON array!");
+        error!("workspace list is not a JSON array!");
         for persistent_work in persistent_workspaces {
             result.push(persistent_work);
         }
@@ -221,390 +843,1280 @@ fn get_persistent_workspaces_list(persistent_workspaces: Vec<serde_json::Value>,
     serde_json::from_value(serde_json::Value::Array(result)).unwrap()
 }
 
-fn get_active_workspace_id() -> Option<i32> {
-    let result = Workspace::get_active();
+/// Properties kept from each monitor returned by "hyprctl -j monitors"
+const MONITOR_KEYS: [&str; 13] = [
+    "id",
+    "name",
+    "description",
+    "width",
+    "height",
+    "refreshRate",
+    "x",
+    "y",
+    "scale",
+    "transform",
+    "focused",
+    "activeWorkspace",
+    "specialWorkspace",
+];
+
+async fn get_monitors_list() -> serde_json::Value {
+    // get list of monitors
+    let object = exec_hyprctl_command("monitors").await;
+
+    let mut result: Vec<serde_json::Value> = vec![];
+
+    if let serde_json::Value::Array(monitors_array) = object {
+        for monitor in monitors_array {
+            if let serde_json::Value::Object(monitor_map) = monitor {
+                // only keep the properties a bar needs to manage per-output windows
+                let mut map_to_insert = serde_json::Map::new();
+                for key in MONITOR_KEYS {
+                    if let Some(value) = monitor_map.get(key) {
+                        map_to_insert.insert(String::from(key), value.clone());
+                    }
+                }
+                result.push(serde_json::Value::Object(map_to_insert));
+            }
+        }
+
+        // sort monitors
+        result.sort_by_key(|m| m.get("id").and_then(|id| id.as_i64()).unwrap_or(0));
+    } else {
+        error!("monitor list is not a JSON array!");
+    }
+
+    serde_json::Value::Array(result)
+}
+
+async fn get_active_workspace_id() -> Option<i32> {
+    let result = get_active_workspace().await;
     match result {
         Ok(work) => Some(work.id),
         err => {
-            eprintln!("error get_active_workspace_id: {err:?}");
+            error!("get_active_workspace_id: {err:?}");
             None
         }
     }
 }
 
-/// Executes "eww get <ewwvar>" and expects the output to be 
-/// a JSON array of objects { "num", "name" }
-fn get_ewwvar_workspaces(ewwvar: &String) -> Option<Vec<serde_json::Value>> {
-    let mut binding = std::process::Command::new(EWW_CMD);
+/// Maximum number of workspace ids kept in the most recently used history
+const HISTORY_LIMIT: usize = 32;
+
+/// Returns the path of a file in this program's runtime directory
+/// for the current Hyprland instance, creating the directory if needed.
+fn get_runtime_path(file_name: &str) -> Option<PathBuf> {
+    let runtime_dir = env::var("XDG_RUNTIME_DIR").ok()?;
+    let signature = instances::get_signature().unwrap_or_default();
+
+    let dir = PathBuf::from(runtime_dir).join("hypr_some_events").join(signature);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        warn!("Could not create runtime directory {:?}: {:?}", dir, e);
+        return None
+    }
+
+    Some(dir.join(file_name))
+}
+
+/// Reads the history saved by a running listener
+/// (none when replaying, the fake instance has no runtime directory).
+fn load_workspace_history() -> Option<Vec<i32>> {
+    if session::is_replaying() {
+        return None
+    }
+    let path = get_runtime_path("history.json")?;
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn save_workspace_history(history: &[i32]) {
+    if session::is_replaying() {
+        return
+    }
+    if let Some(path) = get_runtime_path("history.json") {
+        if let Err(e) = fs::write(&path, serde_json::to_string(history).unwrap()) {
+            warn!("Could not write history file {:?}: {:?}", path, e);
+        }
+    }
+}
+
+/// Moves the workspace id to the front of the history.
+/// Returns true if the history changed.
+fn push_workspace_history(history: &mut Vec<i32>, id: i32) -> bool {
+    if history.first() == Some(&id) {
+        return false
+    }
+
+    history.retain(|h| *h != id);
+    history.insert(0, id);
+    history.truncate(HISTORY_LIMIT);
+    save_workspace_history(history);
+
+    true
+}
+
+/// Replaces "{id}", "{index}" (position in its range, from 1) and "{monitor}" in the pattern
+fn fill_pattern(pattern: &str, id: i64, index: i64, monitor: &str) -> String {
+    pattern
+        .replace("{id}", &id.to_string())
+        .replace("{index}", &index.to_string())
+        .replace("{monitor}", monitor)
+}
+
+/// Returns the persistent workspaces { "id", "name", "monitor", "icon" } declared by the ranges,
+/// name defaults to the id and icon is only set with a pattern.
+fn get_range_workspaces(ranges: &[PersistentRange], name_pattern: &str, icon_pattern: &str) -> Vec<serde_json::Value> {
+    let mut result: Vec<serde_json::Value> = vec![];
+
+    for range in ranges {
+        let monitor = range.monitor.as_deref().unwrap_or("");
+        for id in range.first..=range.last {
+            let index = id - range.first + 1;
+            let mut work = serde_json::Map::new();
+            work.insert(String::from("id"), serde_json::to_value(id).unwrap());
+            let name = if name_pattern.is_empty() {
+                id.to_string()
+            } else {
+                fill_pattern(name_pattern, id, index, monitor)
+            };
+            work.insert(String::from("name"), serde_json::Value::String(name));
+            if !monitor.is_empty() {
+                work.insert(String::from("monitor"), serde_json::Value::String(String::from(monitor)));
+            }
+            if !icon_pattern.is_empty() {
+                let icon = fill_pattern(icon_pattern, id, index, monitor);
+                work.insert(String::from("icon"), serde_json::Value::String(icon));
+            }
+            result.push(serde_json::Value::Object(work));
+        }
+    }
+
+    result
+}
+
+/// Returns the persistent workspaces { "id", "monitor" } of Hyprland's
+/// workspace rules ("hyprctl -j workspacerules"), in the order of the config,
+/// named by the merge only if they aren't live (a live one may have been renamed).
+/// Rules on a named workspace keep the name as "id" ("name:code", "special:scratch"),
+/// rules with selectors ("r[1-5]", "m[DP-1]", ...) match no single workspace and are skipped.
+async fn get_hyprland_persistent_workspaces() -> Vec<serde_json::Value> {
+    let object = exec_hyprctl_command("workspacerules").await;
+
+    let mut result: Vec<serde_json::Value> = vec![];
+    // rules on the same workspace are listed once
+    let mut seen: Vec<String> = vec![];
+
+    if let serde_json::Value::Array(rules_array) = object {
+        for rule in rules_array {
+            if rule.get("persistent").and_then(|v| v.as_bool()) != Some(true) {
+                continue;
+            }
+            let workspace = rule.get("workspaceString").and_then(|v| v.as_str()).unwrap_or("");
+            if workspace.is_empty() || seen.iter().any(|w| w == workspace) {
+                continue;
+            }
+            let single = workspace.parse::<i64>().is_ok()
+                || workspace.starts_with("name:")
+                || workspace == "special"
+                || workspace.starts_with("special:");
+            if !single {
+                debug!("Skipping persistent workspace rule with selectors {:?}", workspace);
+                continue;
+            }
+            seen.push(String::from(workspace));
+
+            let mut work = serde_json::Map::new();
+            let id = match workspace.parse::<i64>() {
+                Ok(id) => serde_json::to_value(id).unwrap(),
+                Err(_) => serde_json::Value::String(String::from(workspace)),
+            };
+            work.insert(String::from("id"), id);
+            if let Some(monitor) = rule.get("monitor").and_then(|v| v.as_str()) {
+                work.insert(String::from("monitor"), serde_json::Value::String(String::from(monitor)));
+            }
+            result.push(serde_json::Value::Object(work));
+        }
+    } else {
+        error!("workspace rule list is not a JSON array!");
+    }
+
+    result
+}
+
+/// Checks a persistent workspace list: a JSON array of objects with an "id" or a "num"
+/// (a workspace id, or a name for named/special workspaces), other properties are kept.
+/// Returns the valid entries, with "id" set from "num" if missing, and why the others are invalid.
+fn normalize_persistent_workspaces(value: serde_json::Value) -> Result<(Vec<serde_json::Value>, Vec<String>), String> {
+    let serde_json::Value::Array(entries) = value else {
+        return Err(format!("expected a JSON array of objects, got {}", value))
+    };
+
+    let mut valid: Vec<serde_json::Value> = vec![];
+    let mut errors: Vec<String> = vec![];
+
+    for (index, mut entry) in entries.into_iter().enumerate() {
+        if let Some(entry_map) = entry.as_object_mut() {
+            match (entry_map.get("id"), entry_map.get("num")) {
+                (None, Some(num)) => {
+                    let num = num.clone();
+                    entry_map.insert(String::from("id"), num);
+                }
+                (Some(id), Some(num)) if id != num => {
+                    errors.push(format!("#{} {}: \"id\" and \"num\" differ", index + 1, entry));
+                    continue;
+                }
+                _ => {}
+            }
+        }
+
+        match get_persistent_key(&entry) {
+            Ok(_) => valid.push(entry),
+            Err(reason) => errors.push(format!("#{} {}: {}", index + 1, entry, reason)),
+        }
+    }
+
+    Ok((valid, errors))
+}
+
+/// Executes "eww get <ewwvar>" and parses the output as JSON
+async fn get_ewwvar_value(ewwvar: &str) -> Result<serde_json::Value, String> {
+    let mut binding = Command::new(EWW_CMD);
     let result = binding
         .arg("get")
         .arg(ewwvar)
-        .output();
+        .output()
+        .await;
 
     match result {
+        Ok(output) if !output.status.success() => Err(format!(
+            "eww get {:?} failed ({}): {}",
+            ewwvar,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
         Ok(output) => {
-            let output_string = String::from_utf8(output.stdout).unwrap();
-            match serde_json::from_str(output_string.as_str()) {
-                Ok(output_value) => {
-                    if let serde_json::Value::Array(output_vec) = output_value {
-                        return Some(output_vec)
-                    } else {
-                        return None
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Could not parse value of eww var {:?}: {:?}", ewwvar, e);
-                    None
-                }
+            let output_string = String::from_utf8(output.stdout).unwrap_or_default();
+            serde_json::from_str(output_string.as_str())
+                .map_err(|e| format!("Could not parse value of eww var {:?}: {:?}", ewwvar, e))
+        },
+        Err(e) => Err(format!("Could not execute command: eww get {:?}: {:?}", ewwvar, e)),
+    }
+}
+
+/// Returns the valid persistent workspaces of the eww variable (see `normalize_persistent_workspaces`),
+/// reporting the invalid ones on stderr. `None` if the variable can't be used at all.
+async fn get_ewwvar_workspaces(ewwvar: &str) -> Option<Vec<serde_json::Value>> {
+    let value = match get_ewwvar_value(ewwvar).await {
+        Ok(value) => value,
+        Err(e) => {
+            error!("{}", e);
+            return None
+        }
+    };
+
+    match normalize_persistent_workspaces(value) {
+        Ok((valid, errors)) => {
+            for error in errors {
+                warn!("eww var {:?}: ignoring persistent workspace {}", ewwvar, error);
             }
+            Some(valid)
         },
         Err(e) => {
-            eprintln!("Could not execute command: eww get {:?}: {:?}", ewwvar, e);
+            error!("eww var {:?}: {}, showing the live workspaces only", ewwvar, e);
             None
         }
     }
 }
 
+/// Validates the eww variable and/or the rules file of the config,
+/// printing what is wrong, without starting any listener.
+pub async fn check(ewwvar: String, rules: String) -> Result<(), Box<dyn Error>> {
+    if ewwvar.is_empty() && rules.is_empty() {
+        return Err("Nothing to check, use --eww=<var> and/or --rules=<file>".into())
+    }
+
+    let mut valid = true;
+
+    if !ewwvar.is_empty() {
+        let checked = get_ewwvar_value(&ewwvar).await.and_then(normalize_persistent_workspaces);
+        match checked {
+            Ok((entries, errors)) => {
+                for error in &errors {
+                    output!("eww var {:?}: invalid persistent workspace {}", ewwvar, error);
+                }
+                output!("eww var {:?}: {} valid persistent workspace(s), {} invalid", ewwvar, entries.len(), errors.len());
+                valid &= errors.is_empty();
+            },
+            Err(e) => {
+                output!("eww var {:?}: {}", ewwvar, e);
+                valid = false;
+            }
+        }
+    }
+
+    if !rules.is_empty() {
+        match load_workspace_rules(&rules) {
+            Ok(_) => output!("rules {:?}: valid", rules),
+            Err(e) => {
+                output!("{}", e);
+                valid = false;
+            }
+        }
+    }
+
+    if valid {
+        Ok(())
+    } else {
+        Err("Check failed".into())
+    }
+}
+
 /// Displays workspaces as JSON if new (focused) workspaces.
-fn display_persistent_workspaces_maybe(previous_active_ws_id: &Option<i32>, persistent_workspaces: Vec<serde_json::Value>) -> i32 {
-    let work_id = get_active_workspace_id();
+async fn display_persistent_workspaces_maybe(previous_active_ws_id: &Option<i32>, persistent_workspaces: Vec<serde_json::Value>, merge: Merge, view: &View, history: &mut Vec<i32>) -> i32 {
+    let work_id = get_active_workspace_id().await;
 
     let default_ws_id = i64::from(0);
 
     match work_id {
         Some(id) => {
+            push_workspace_history(history, id);
             if let Some(arg_id) = previous_active_ws_id {
                 if *arg_id != id {
-                    let result = get_persistent_workspaces_list(persistent_workspaces, i64::from(id)).to_string();
+                    let result = get_persistent_workspaces_list(persistent_workspaces, i64::from(id), merge, view, history).await.to_string();
                     if !result.is_empty() {
-                        println!("{}", result);
-                        //println!("Display now!");
+                        output!("{}", result);
                     }
                 } else {
-                    eprintln!("Still same workspace");
+                    debug!("Still same workspace");
                 }
             } else {
-                let result = get_persistent_workspaces_list(persistent_workspaces, i64::from(id)).to_string();
+                let result = get_persistent_workspaces_list(persistent_workspaces, i64::from(id), merge, view, history).await.to_string();
                 if !result.is_empty() {
-                    println!("{}", result);
-                    //println!("Display now!");
+                    output!("{}", result);
                 }
             }
             id
         }
         None => {
-            eprintln!("error - could not get active workspace");
-            let result = get_workspaces_list(default_ws_id).to_string();
+            error!("could not get active workspace");
+            let result = get_workspaces_list(default_ws_id, view, history, None).await.to_string();
             if !result.is_empty() {
-                println!("{}", result);
-                //println!("Display now!");
+                output!("{}", result);
             }
-            i32::from(0)
+            0
         }
     }
 }
 
 /// Displays workspaces as JSON if new (focused) workspaces.
-fn display_workspaces_maybe(previous_active_ws_id: &Option<i32>) -> i32 {
-    let work_id = get_active_workspace_id();
+async fn display_workspaces_maybe(previous_active_ws_id: &Option<i32>, view: &View, history: &mut Vec<i32>, rules: Option<&serde_json::Value>) -> i32 {
+    let work_id = get_active_workspace_id().await;
 
     let default_ws_id = i64::from(0);
 
     match work_id {
         Some(id) => {
+            push_workspace_history(history, id);
             if let Some(arg_id) = previous_active_ws_id {
                 if *arg_id != id {
-                    let result = get_workspaces_list(i64::from(id)).to_string();
+                    let result = get_workspaces_list(i64::from(id), view, history, rules).await.to_string();
                     if !result.is_empty() {
-                        println!("{}", result);
-                        //println!("Display now!");
+                        output!("{}", result);
                     }
                 } else {
-                    eprintln!("Still same workspace");
+                    debug!("Still same workspace");
                 }
             } else {
-                let result = get_workspaces_list(i64::from(id)).to_string();
+                let result = get_workspaces_list(i64::from(id), view, history, rules).await.to_string();
                 if !result.is_empty() {
-                    println!("{}", result);
-                    //println!("Display now!");
+                    output!("{}", result);
                 }
             }
             id
         }
         None => {
-            eprintln!("error - could not get active workspace");
-            let result = get_workspaces_list(default_ws_id).to_string();
+            error!("could not get active workspace");
+            let result = get_workspaces_list(default_ws_id, view, history, rules).await.to_string();
             if !result.is_empty() {
-                println!("{}", result);
-                //println!("Display now!");
+                output!("{}", result);
             }
-            i32::from(0)
+            0
         }
     }
 }
 
-/// Displays active workspace as JSON if new (focused) workspace.
-fn display_active_workspace_maybe(previous_active_ws_id: &Option<i32>) -> i32 {
-    let result = Workspace::get_active();
-    let workspace = match result {
-        Ok(work) => Some(work),
-        err => {
-            eprintln!("error display_active_workspace_maybe: {err:?}");
-            None
+/// Returns the active workspace as JSON
+/// { "id", "name", "monitor", "windows", "lastwindowtitle", "special", "previous" }.
+fn get_active_workspace_json(work: &Workspace, previous_workspace: Option<i32>) -> serde_json::Value {
+    let mut output = serde_json::Map::new();
+
+    output.insert(String::from("id"), serde_json::to_value(work.id).unwrap());
+    output.insert(String::from("name"), serde_json::to_value(&work.name).unwrap());
+    output.insert(String::from("monitor"), serde_json::to_value(&work.monitor).unwrap());
+    output.insert(String::from("windows"), serde_json::to_value(work.windows).unwrap());
+    output.insert(String::from("lastwindowtitle"), serde_json::to_value(&work.last_window_title).unwrap());
+    output.insert(String::from("special"), serde_json::Value::Bool(work.name.starts_with("special")));
+    output.insert(String::from("previous"), serde_json::to_value(previous_workspace).unwrap());
+
+    serde_json::Value::Object(output)
+}
+
+/// Displays active workspace id if new (focused) workspace.
+/// Displays "unknown" if it can't be known,
+/// and returns `None` so the next refresh displays anyway.
+async fn display_active_workspace_maybe(previous_active_ws_id: &Option<i32>, last_active: Option<i32>, previous_workspace: &mut Option<i32>) -> Option<i32> {
+    let work = match get_active_workspace().await {
+        Ok(work) => work,
+        Err(e) => {
+            error!("display_active_workspace_maybe: {e:?}");
+            output!("unknown");
+            return None
         }
     };
 
-    if let Some(work) = workspace {
-        if let Some(arg_id) = previous_active_ws_id {
-            if *arg_id != work.id {
-                println!("{}", work.id);
-            } else {
-                eprintln!("Still same workspace");
+    track_previous_workspace(last_active, work.id, previous_workspace);
+
+    if *previous_active_ws_id == Some(work.id) {
+        debug!("Still same workspace");
+    } else {
+        output!("{}", work.id);
+    }
+
+    Some(work.id)
+}
+
+/// Keeps the workspace focused before the active one
+fn track_previous_workspace(last_active: Option<i32>, id: i32, previous_workspace: &mut Option<i32>) {
+    if let Some(last) = last_active {
+        if last != id {
+            *previous_workspace = Some(last);
+        }
+    }
+}
+
+/// Same as `display_active_workspace_maybe` for an id given by the event, without querying it
+fn display_active_workspace_id(previous_active_ws_id: &Option<i32>, id: i32, last_active: Option<i32>, previous_workspace: &mut Option<i32>) -> Option<i32> {
+    track_previous_workspace(last_active, id, previous_workspace);
+
+    if *previous_active_ws_id == Some(id) {
+        debug!("Still same workspace");
+    } else {
+        output!("{}", id);
+    }
+
+    Some(id)
+}
+
+/// Workspaces, merged with the persistent ones from the eww variable,
+/// the ranges or Hyprland's workspace rules if any
+struct WorkspaceStream {
+    persistent_workspaces: Option<Vec<serde_json::Value>>,
+    /// persistent workspaces come from Hyprland's workspace rules, reloaded with the config
+    from_hyprland: bool,
+    /// what to do with the live workspaces which are not persistent
+    merge: Merge,
+    /// label/icon rules, without persistent workspaces
+    rules: Option<serde_json::Value>,
+    view: View,
+    history: Vec<i32>,
+    last_active: Option<i32>,
+}
+
+impl Stream for WorkspaceStream {
+    const TRIGGERS: &'static [(Trigger, Refresh)] = &[
+        // Shows when active window changes
+        (Trigger::ActiveWindow, Refresh::IfChanged),
+        (Trigger::Workspace, Refresh::Always),
+        (Trigger::WorkspaceAdded, Refresh::Always),
+        (Trigger::WorkspaceMoved, Refresh::Always),
+        (Trigger::WorkspaceDestroyed, Refresh::Always),
+        // monitor change
+        (Trigger::ActiveMonitor, Refresh::IfChanged),
+        // special workspace shown/hidden
+        (Trigger::ActiveSpecial, Refresh::Always),
+        (Trigger::WorkspaceRenamed, Refresh::Always),
+        // window count
+        (Trigger::WindowOpened, Refresh::Always),
+        (Trigger::WindowClosed, Refresh::Always),
+        (Trigger::WindowMoved, Refresh::Always),
+        (Trigger::ConfigReloaded, Refresh::Reload),
+    ];
+
+    async fn refresh(&mut self, refresh: Refresh, _: Option<i32>) {
+        if refresh == Refresh::Reload && self.from_hyprland {
+            self.persistent_workspaces = Some(get_hyprland_persistent_workspaces().await);
+        }
+
+        let previous = refresh.previous(self.last_active);
+        let result = match &self.persistent_workspaces {
+            Some(v) => display_persistent_workspaces_maybe(&previous, v.to_vec(), self.merge, &self.view, &mut self.history).await,
+            None => display_workspaces_maybe(&previous, &self.view, &mut self.history, self.rules.as_ref()).await
+        };
+        // Keep id from last active ws in sync
+        self.last_active = Some(result);
+    }
+}
+
+pub async fn subscribe_to_workspace_eww(ewwvar: String, merge: Merge, view: View) -> hyprland::Result<()> {
+    run_stream(WorkspaceStream {
+        // Expect a Vec of serde_json::Value (objects)
+        persistent_workspaces: get_ewwvar_workspaces(&ewwvar).await,
+        from_hyprland: false,
+        merge,
+        rules: None,
+        view,
+        history: load_workspace_history().unwrap_or_default(),
+        last_active: None,
+    }).await
+}
+
+pub async fn subscribe_to_workspace_ranges(ranges: Vec<PersistentRange>, name_pattern: String, icon_pattern: String, merge: Merge, view: View) -> hyprland::Result<()> {
+    run_stream(WorkspaceStream {
+        persistent_workspaces: Some(get_range_workspaces(&ranges, &name_pattern, &icon_pattern)),
+        from_hyprland: false,
+        merge,
+        rules: None,
+        view,
+        history: load_workspace_history().unwrap_or_default(),
+        last_active: None,
+    }).await
+}
+
+pub async fn subscribe_to_workspace_hyprland(merge: Merge, view: View) -> hyprland::Result<()> {
+    run_stream(WorkspaceStream {
+        persistent_workspaces: Some(get_hyprland_persistent_workspaces().await),
+        from_hyprland: true,
+        merge,
+        rules: None,
+        view,
+        history: load_workspace_history().unwrap_or_default(),
+        last_active: None,
+    }).await
+}
+
+pub async fn subscribe_to_workspace(view: View, rules: Option<serde_json::Value>) -> hyprland::Result<()> {
+    run_stream(WorkspaceStream {
+        persistent_workspaces: None,
+        from_hyprland: false,
+        merge: Merge::None,
+        rules,
+        view,
+        history: load_workspace_history().unwrap_or_default(),
+        last_active: None,
+    }).await
+}
+
+/// Id of the active workspace
+struct ActiveWorkspaceStream {
+    last_active: Option<i32>,
+    /// id of the workspace focused before the active one
+    previous_workspace: Option<i32>,
+}
+
+impl Stream for ActiveWorkspaceStream {
+    const TRIGGERS: &'static [(Trigger, Refresh)] = &[
+        (Trigger::ActiveWindow, Refresh::IfChanged),
+        (Trigger::Workspace, Refresh::Always),
+        (Trigger::WorkspaceAdded, Refresh::Always),
+        (Trigger::WorkspaceMoved, Refresh::Always),
+        (Trigger::WorkspaceDestroyed, Refresh::Always),
+        (Trigger::ActiveMonitor, Refresh::IfChanged),
+        (Trigger::ActiveSpecial, Refresh::Always),
+        (Trigger::ConfigReloaded, Refresh::Reload),
+    ];
+
+    async fn refresh(&mut self, refresh: Refresh, active_id: Option<i32>) {
+        let previous = refresh.previous(self.last_active);
+        self.last_active = match active_id {
+            // the id is all the output needs
+            Some(id) => display_active_workspace_id(&previous, id, self.last_active, &mut self.previous_workspace),
+            None => display_active_workspace_maybe(&previous, self.last_active, &mut self.previous_workspace).await,
+        };
+    }
+}
+
+/// Active workspace as JSON, displayed when any of its properties changed
+struct ActiveWorkspaceJsonStream {
+    last_active: Option<i32>,
+    /// id of the workspace focused before the active one
+    previous_workspace: Option<i32>,
+    last_output: String,
+}
+
+impl Stream for ActiveWorkspaceJsonStream {
+    const TRIGGERS: &'static [(Trigger, Refresh)] = &[
+        (Trigger::ActiveWindow, Refresh::IfChanged),
+        (Trigger::Workspace, Refresh::IfChanged),
+        (Trigger::WorkspaceMoved, Refresh::IfChanged),
+        (Trigger::ActiveMonitor, Refresh::IfChanged),
+        (Trigger::ActiveSpecial, Refresh::IfChanged),
+        (Trigger::WorkspaceRenamed, Refresh::IfChanged),
+        // "windows" and "lastwindowtitle"
+        (Trigger::WindowOpened, Refresh::IfChanged),
+        (Trigger::WindowClosed, Refresh::IfChanged),
+        (Trigger::WindowMoved, Refresh::IfChanged),
+        (Trigger::WindowTitle, Refresh::IfChanged),
+    ];
+
+    async fn refresh(&mut self, _: Refresh, _: Option<i32>) {
+        let work = match get_active_workspace().await {
+            Ok(work) => work,
+            Err(e) => {
+                error!("ActiveWorkspaceJsonStream: {e:?}");
+                let mut output = serde_json::Map::new();
+                output.insert(String::from("id"), serde_json::Value::Null);
+                output.insert(String::from("error"), serde_json::Value::String(e.to_string()));
+                output!("{}", serde_json::Value::Object(output));
+                // the next refresh displays anyway
+                self.last_active = None;
+                self.last_output.clear();
+                return
             }
+        };
+
+        track_previous_workspace(self.last_active, work.id, &mut self.previous_workspace);
+        self.last_active = Some(work.id);
+
+        let output = get_active_workspace_json(&work, self.previous_workspace).to_string();
+        if output == self.last_output {
+            debug!("Still same workspace");
         } else {
-            println!("{}", work.id);
+            output!("{}", output);
+            self.last_output = output;
+        }
+    }
+}
+
+pub async fn subscribe_to_active_workspace(json: bool) -> hyprland::Result<()> {
+    // the MRU history knows the previous workspace from before start
+    let previous_workspace = load_workspace_history().unwrap_or_default().get(1).copied();
+    if json {
+        run_stream(ActiveWorkspaceJsonStream {
+            last_active: None,
+            previous_workspace,
+            last_output: String::new(),
+        }).await
+    } else {
+        run_stream(ActiveWorkspaceStream {
+            last_active: None,
+            previous_workspace,
+        }).await
+    }
+}
+
+/// Displays most recently used workspace ids as JSON if the history changed.
+async fn display_workspace_history_maybe(history: &mut Vec<i32>, refresh: Refresh, active_id: Option<i32>) {
+    let active_id = match active_id {
+        Some(id) => Some(id),
+        None => get_active_workspace_id().await,
+    };
+    match active_id {
+        Some(id) => {
+            if push_workspace_history(history, id) || refresh == Refresh::Always {
+                output!("{}", serde_json::to_string(history).unwrap());
+            } else {
+                debug!("Still same workspace");
+            }
         }
-        return work.id
+        None => error!("could not get active workspace")
+    }
+}
+
+/// Displays the history kept by a running listener once.
+pub async fn display_workspace_history() {
+    let mut history = load_workspace_history().unwrap_or_default();
+    if let Some(id) = get_active_workspace_id().await {
+        history.retain(|h| *h != id);
+        history.insert(0, id);
+    }
+    output!("{}", serde_json::to_string(&history).unwrap());
+}
+
+/// Most recently used workspace ids
+struct HistoryStream {
+    history: Vec<i32>,
+}
+
+impl Stream for HistoryStream {
+    const TRIGGERS: &'static [(Trigger, Refresh)] = &[
+        // Shows when active window changes
+        (Trigger::ActiveWindow, Refresh::IfChanged),
+        (Trigger::Workspace, Refresh::IfChanged),
+        // monitor change
+        (Trigger::ActiveMonitor, Refresh::IfChanged),
+    ];
+
+    async fn refresh(&mut self, refresh: Refresh, active_id: Option<i32>) {
+        display_workspace_history_maybe(&mut self.history, refresh, active_id).await;
+    }
+}
+
+pub async fn subscribe_to_workspace_history() -> hyprland::Result<()> {
+    run_stream(HistoryStream {
+        history: load_workspace_history().unwrap_or_default(),
+    }).await
+}
+
+/// Returns the window address the way "hyprctl -j clients" does (with "0x")
+fn format_address(address: &str) -> String {
+    if address.starts_with("0x") {
+        String::from(address)
     } else {
-        println!("{}", 0);
-        return i32::from(0)
+        format!("0x{address}")
     }
 }
 
-pub fn subscribe_to_workspace_eww(ewwvar: String) -> hyprland::Result<()> {
+/// Displays a window event as JSON { "event", "time", "address", ... }.
+fn display_window_event(event: &str, address: &str, data: serde_json::Map<String, serde_json::Value>) {
+    let mut output = serde_json::Map::new();
+
+    output.insert(String::from("event"), serde_json::to_value(event).unwrap());
+    output.insert(String::from("time"), serde_json::to_value(get_timestamp()).unwrap());
+    output.insert(String::from("address"), serde_json::to_value(format_address(address)).unwrap());
+    assign_map(&mut output, &data);
 
-    // Expect a Vec of serde_json::Value (objects)
-    let ewwvar_value = Arc::new(Mutex::new(get_ewwvar_workspaces(&ewwvar)));
-    let ewwvar_value_a = ewwvar_value.clone();
-    let ewwvar_value_b = ewwvar_value.clone();
-    let ewwvar_value_c = ewwvar_value.clone();
-    let ewwvar_value_d = ewwvar_value.clone();
-    let ewwvar_value_e = ewwvar_value.clone();
-    let ewwvar_value_f = ewwvar_value.clone();
+    output!("{}", serde_json::Value::Object(output))
+}
+
+/// Returns the window event ("open", "close" or "move") and its data from an event of the socket
+fn get_window_event(name: &str, value: &serde_json::Value) -> Option<(&'static str, serde_json::Map<String, serde_json::Value>)> {
+    let mut data = serde_json::Map::new();
+    match name {
+        "openwindow" => {
+            data.insert(String::from("workspace"), value["workspace"].clone());
+            data.insert(String::from("class"), value["class"].clone());
+            data.insert(String::from("title"), value["title"].clone());
+            Some(("open", data))
+        }
+        "closewindow" => Some(("close", data)),
+        "movewindow" => {
+            data.insert(String::from("workspace"), value["workspace"].clone());
+            Some(("move", data))
+        }
+        "movewindowv2" => {
+            data.insert(String::from("workspace"), value["name"].clone());
+            Some(("move", data))
+        }
+        _ => None,
+    }
+}
 
-    // Display one time and retrieve active ws id
-    let first_result = match &ewwvar_value.lock().unwrap().as_deref() {
-        Some(v) => display_persistent_workspaces_maybe(&None, v.to_vec()),
-        None => display_workspaces_maybe(&None)
+pub async fn subscribe_to_windows() -> hyprland::Result<()> {
+    let mut lines = events::connect_events().await?;
+    let mut v2_filter = events::V2Filter::default();
+
+    while let Some(line) = lines.next_line().await? {
+        let Some((name, value)) = events::parse_event(&line) else { continue };
+        for (name, value) in v2_filter.filter(name, value) {
+            // keeps --cache in sync for --notify
+            cache::apply(&name, &value);
+            if let Some((event, data)) = get_window_event(&name, &value) {
+                display_window_event(event, value["address"].as_str().unwrap_or_default(), data);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Displays monitors as JSON.
+async fn display_monitors() {
+    output!("{}", get_monitors_list().await);
+}
+
+/// Monitors, displayed on every trigger
+struct MonitorsStream;
+
+impl Stream for MonitorsStream {
+    const TRIGGERS: &'static [(Trigger, Refresh)] = &[
+        (Trigger::MonitorAdded, Refresh::Always),
+        (Trigger::MonitorRemoved, Refresh::Always),
+        (Trigger::ActiveMonitor, Refresh::Always),
+        // active workspace of a monitor changes
+        (Trigger::Workspace, Refresh::Always),
+        (Trigger::WorkspaceMoved, Refresh::Always),
+        (Trigger::ActiveSpecial, Refresh::Always),
+    ];
+
+    async fn refresh(&mut self, _: Refresh, _: Option<i32>) {
+        display_monitors().await;
+    }
+}
+
+pub async fn subscribe_to_monitors() -> hyprland::Result<()> {
+    run_stream(MonitorsStream).await
+}
+
+/// Returns { "fullscreen", "floating" } for the focused workspace and window
+async fn get_fullscreen_state() -> serde_json::Value {
+    let fullscreen = match get_active_workspace().await {
+        Ok(work) => work.fullscreen,
+        err => {
+            error!("get_fullscreen_state: {err:?}");
+            false
+        }
     };
 
-    // Keep id from last active ws in sync
-    let last_active = Arc::new(Mutex::new(Some(first_result)));
-    let last_active_a = last_active.clone();
-    let last_active_b = last_active.clone();
-    let last_active_c = last_active.clone();
-    let last_active_d = last_active.clone();
-    let last_active_e = last_active.clone();
-    let last_active_f = last_active.clone();
-
-    // Create a event listener
-    let mut event_listener = EventListener::new();
-
-    // Shows when active window changes
-    event_listener.add_active_window_change_handler(move |_, _| {
-        let result = match &ewwvar_value_a.lock().unwrap().as_deref() {
-            Some(v) => display_persistent_workspaces_maybe(&last_active_a.lock().unwrap(), v.to_vec()),
-            None => display_workspaces_maybe(&last_active_a.lock().unwrap())
-        };
-        last_active_a.lock().unwrap().replace(result);
-    });
+    // "hyprctl -j activewindow" returns an empty object when no window is focused
+    let window = exec_hyprctl_command("activewindow").await;
+    let floating = window
+        .get("floating")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
 
-    event_listener.add_workspace_change_handler(move |_, _| {
-        let result = match &ewwvar_value_b.lock().unwrap().as_deref() {
-            Some(v) => display_persistent_workspaces_maybe(&None, v.to_vec()),
-            None => display_workspaces_maybe(&None)
-        };
-        last_active_b.lock().unwrap().replace(result);
-    });
+    let mut output = serde_json::Map::new();
+    output.insert(String::from("fullscreen"), serde_json::Value::Bool(fullscreen));
+    output.insert(String::from("floating"), serde_json::Value::Bool(floating));
 
-    event_listener.add_workspace_added_handler(move |_, _| {
-        let result = match &ewwvar_value_c.lock().unwrap().as_deref() {
-            Some(v) => display_persistent_workspaces_maybe(&None, v.to_vec()),
-            None => display_workspaces_maybe(&None)
-        };
-        last_active_c.lock().unwrap().replace(result);
-    });
+    serde_json::Value::Object(output)
+}
 
-    event_listener.add_workspace_moved_handler(move |_, _| {
-        let result = match &ewwvar_value_d.lock().unwrap().as_deref() {
-            Some(v) => display_persistent_workspaces_maybe(&None, v.to_vec()),
-            None => display_workspaces_maybe(&None)
-        };
-        last_active_d.lock().unwrap().replace(result);
-    });
+/// Displays fullscreen/floating state as JSON if it changed.
+async fn display_fullscreen_state_maybe(previous_state: &str) -> String {
+    let result = get_fullscreen_state().await.to_string();
+    if result != previous_state {
+        output!("{}", result);
+    }
+    result
+}
 
-    event_listener.add_workspace_destroy_handler(move |_, _| {
-        let result = match &ewwvar_value_e.lock().unwrap().as_deref() {
-            Some(v) => display_persistent_workspaces_maybe(&None, v.to_vec()),
-            None => display_workspaces_maybe(&None)
-        };
-        last_active_e.lock().unwrap().replace(result);
-    });
+/// Fullscreen/floating state, displayed when it changed
+struct FullscreenStream {
+    last_state: String,
+}
 
-    // monitor change
-    event_listener.add_active_monitor_change_handler(move |_, _| {
-        let result = match &ewwvar_value_f.lock().unwrap().as_deref() {
-            Some(v) => display_persistent_workspaces_maybe(&last_active_f.lock().unwrap(), v.to_vec()),
-            None => display_workspaces_maybe(&None)
-        };
-        last_active_f.lock().unwrap().replace(result);
-    });
+impl Stream for FullscreenStream {
+    const TRIGGERS: &'static [(Trigger, Refresh)] = &[
+        (Trigger::Fullscreen, Refresh::IfChanged),
+        (Trigger::FloatState, Refresh::IfChanged),
+        // Shows when active window changes
+        (Trigger::ActiveWindow, Refresh::IfChanged),
+        (Trigger::Workspace, Refresh::IfChanged),
+        // monitor change
+        (Trigger::ActiveMonitor, Refresh::IfChanged),
+    ];
+
+    async fn refresh(&mut self, _: Refresh, _: Option<i32>) {
+        // Keep last displayed state in sync
+        self.last_state = display_fullscreen_state_maybe(&self.last_state).await;
+    }
+}
 
-    // and execute the function
-    // here we are using the blocking variant
-    // but there is a async version too
-    event_listener.start_listener()
+pub async fn subscribe_to_fullscreen() -> hyprland::Result<()> {
+    run_stream(FullscreenStream { last_state: String::new() }).await
 }
 
-pub fn subscribe_to_workspace() -> hyprland::Result<()> {
-    // Display one time and retrieve active ws id
-    let first_result = display_workspaces_maybe(&None);
+/// Executes "hyprctl submap" and returns the name of the current submap.
+/// Falls back to "default" when the command is not supported.
+/// Recorded (and replayed) as the query "submap" with the name as output.
+async fn get_current_submap() -> String {
+    if let Some(value) = session::replayed_query("submap") {
+        return String::from(value.as_str().unwrap_or("default"))
+    }
 
-    // Keep id from last active ws in sync
-    let last_active = Arc::new(Mutex::new(Some(first_result)));
-    let last_active_a = last_active.clone();
-    let last_active_b = last_active.clone();
-    let last_active_c = last_active.clone();
-    let last_active_d = last_active.clone();
-    let last_active_e = last_active.clone();
-    let last_active_f = last_active.clone();
+    let result = hyprctl().arg("submap").output().await;
 
-    // Create a event listener
-    let mut event_listener = EventListener::new();
+    let name = match result {
+        Ok(output) => {
+            let output_string = String::from_utf8(output.stdout).unwrap_or_default();
+            let name = output_string.trim();
+            if name.is_empty() || name.contains(' ') {
+                // empty or an error message such as "unknown request"
+                String::from("default")
+            } else {
+                String::from(name)
+            }
+        }
+        Err(e) => {
+            error!("Could not execute command: hyprctl submap: {:?}", e);
+            String::from("default")
+        }
+    };
+    session::record_query("submap", &serde_json::Value::String(name.clone()));
+    name
+}
 
-    // Shows when active window changes
-    event_listener.add_active_window_change_handler(move |_, _| {
-        let result = display_workspaces_maybe(&last_active_a.lock().unwrap());
-        last_active_a.lock().unwrap().replace(result);
-    });
+/// Returns the keybinds from "hyprctl -j binds" belonging to the submap.
+async fn get_submap_binds(name: &str) -> serde_json::Value {
+    // binds of the default submap have an empty "submap" property
+    let submap = if name == "default" { "" } else { name };
 
-    event_listener.add_workspace_change_handler(move |_, _| {
-        let result = display_workspaces_maybe(&None);
-        last_active_b.lock().unwrap().replace(result);
-    });
+    let object = exec_hyprctl_command("binds").await;
 
-    event_listener.add_workspace_added_handler(move |_, _| {
-        let result = display_workspaces_maybe(&None);
-        last_active_c.lock().unwrap().replace(result);
-    });
+    let mut result: Vec<serde_json::Value> = vec![];
 
-    event_listener.add_workspace_moved_handler(move |_, _| {
-        let result = display_workspaces_maybe(&None);
-        last_active_d.lock().unwrap().replace(result);
-    });
+    if let serde_json::Value::Array(binds_array) = object {
+        for bind in binds_array {
+            if bind.get("submap").and_then(|v| v.as_str()) == Some(submap) {
+                result.push(bind);
+            }
+        }
+    } else {
+        error!("bind list is not a JSON array!");
+    }
 
-    event_listener.add_workspace_destroy_handler(move |_, _| {
-        let result = display_workspaces_maybe(&None);
-        last_active_e.lock().unwrap().replace(result);
-    });
+    serde_json::Value::Array(result)
+}
 
-    // monitor change
-    event_listener.add_active_monitor_change_handler(move |_, _| {
-        let result = display_workspaces_maybe(&last_active_f.lock().unwrap());
-        last_active_f.lock().unwrap().replace(result);
-    });
+fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Displays submap as JSON { "name", "previous", "since", "binds"? }.
+async fn display_submap(name: &str, previous: Option<&str>, with_binds: bool) {
+    let mut output = serde_json::Map::new();
 
-    // and execute the function
-    // here we are using the blocking variant
-    // but there is a async version too
-    event_listener.start_listener()
+    output.insert(String::from("name"), serde_json::to_value(name).unwrap());
+    output.insert(String::from("previous"), serde_json::to_value(previous).unwrap());
+    output.insert(String::from("since"), serde_json::to_value(get_timestamp()).unwrap());
+
+    if with_binds {
+        output.insert(String::from("binds"), get_submap_binds(name).await);
+    }
+
+    output!("{}", serde_json::Value::Object(output))
 }
 
-pub fn subscribe_to_active_workspace() -> hyprland::Result<()> {
-    // Display one time and retrieve active ws id
-    let first_result = display_active_workspace_maybe(&None);
+pub async fn subscribe_to_submap(with_binds: bool) -> hyprland::Result<()> {
+    // connect first so no change is missed while displaying
+    let mut lines = events::connect_events().await?;
 
-    // Keep id from last active ws in sync
-    let last_active = Arc::new(Mutex::new(Some(first_result)));
-    let last_active_a = last_active.clone();
-    let last_active_b = last_active.clone();
-    let last_active_c = last_active.clone();
-    let last_active_d = last_active.clone();
-    let last_active_e = last_active.clone();
-    let last_active_f = last_active.clone();
+    // Display one time and retrieve current submap
+    let mut last_submap = get_current_submap().await;
+    display_submap(&last_submap, None, with_binds).await;
 
-    // Create a event listener
-    let mut event_listener = EventListener::new();
+    let mut v2_filter = events::V2Filter::default();
 
-    // Shows when active window changes
-    event_listener.add_active_window_change_handler(move |_, _| {
-        let result = display_active_workspace_maybe(&last_active_a.lock().unwrap());
-        last_active_a.lock().unwrap().replace(result);
-    });
+    while let Some(line) = lines.next_line().await? {
+        let Some((name, data)) = events::parse_event(&line) else { continue };
+        for (name, data) in v2_filter.filter(name, data) {
+            // keeps --cache in sync for --notify
+            cache::apply(&name, &data);
+            if name != "submap" {
+                continue;
+            }
 
-    event_listener.add_workspace_change_handler(move |_, _| {
-        let result = display_active_workspace_maybe(&None);
-        last_active_b.lock().unwrap().replace(result);
-    });
+            let submap = match data["name"].as_str() {
+                Some(value) if !value.is_empty() => String::from(value),
+                _ => String::from("default"),
+            };
+            display_submap(&submap, Some(&last_submap), with_binds).await;
+            last_submap = submap;
+        }
+    }
 
-    event_listener.add_workspace_added_handler(move |_, _| {
-        let result = display_active_workspace_maybe(&None);
-        last_active_c.lock().unwrap().replace(result);
-    });
+    Ok(())
+}
 
-    event_listener.add_workspace_moved_handler(move |_, _| {
-        let result = display_active_workspace_maybe(&None);
-        last_active_d.lock().unwrap().replace(result);
-    });
+/// Sends a desktop notification when entering/leaving a submap
+/// or when a window asks for attention, until the event socket closes.
+async fn notify_changes() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let notifier = notify::Notifier::connect().await?;
+    let mut lines = events::connect_events().await?;
+
+    let mut notifications = notify::Notifications::new(notifier, get_current_submap().await);
+
+    while let Some(line) = lines.next_line().await? {
+        let Some((name, data)) = events::parse_event(&line) else { continue };
+
+        let result = match name.as_str() {
+            "submap" => notifications.submap(data["name"].as_str().unwrap_or_default()).await,
+            "urgent" => {
+                let address = format_address(data["address"].as_str().unwrap_or(""));
+                let clients = exec_hyprctl_command("clients").await;
+                let body = clients
+                    .as_array()
+                    .and_then(|clients| clients.iter().find(|c| c["address"] == address.as_str()))
+                    .map(|c| format!("{}: {}", c["class"].as_str().unwrap_or(""), c["title"].as_str().unwrap_or("")))
+                    .unwrap_or(address);
+                notifications.urgent(&body).await
+            }
+            _ => Ok(()),
+        };
 
-    event_listener.add_workspace_destroy_handler(move |_, _| {
-        let result = display_active_workspace_maybe(&None);
-        last_active_e.lock().unwrap().replace(result);
-    });
+        if let Err(e) = result {
+            warn!("Could not send notification: {:?}", e);
+        }
+    }
 
-    // monitor change
-    event_listener.add_active_monitor_change_handler(move |_, _| {
-        let result = display_active_workspace_maybe(&last_active_f.lock().unwrap());
-        last_active_f.lock().unwrap().replace(result);
-    });
+    Ok(())
+}
+
+/// Displays every event from the event socket as JSON { "event", "data" },
+/// keeping only events named in "only" (if any) and not named in "exclude".
+pub async fn subscribe_to_raw(only: Vec<String>, exclude: Vec<String>) -> hyprland::Result<()> {
+    let mut lines = events::connect_events().await?;
+    let mut v2_filter = events::V2Filter::default();
+
+    while let Some(line) = lines.next_line().await? {
+        match events::parse_event(&line) {
+            Some((name, data)) => {
+                // keeps --cache in sync for --notify, all events are still displayed
+                for (name, data) in v2_filter.filter(name.clone(), data.clone()) {
+                    cache::apply(&name, &data);
+                }
+                if (!only.is_empty() && !only.contains(&name)) || exclude.contains(&name) {
+                    continue;
+                }
+
+                let mut output = serde_json::Map::new();
+                output.insert(String::from("event"), serde_json::Value::String(name));
+                output.insert(String::from("data"), data);
+
+                output!("{}", serde_json::Value::Object(output))
+            }
+            None => warn!("Could not parse event: {:?}", line)
+        }
+    }
 
-    // and execute the function
-    // here we are using the blocking variant
-    // but there is a async version too
-    event_listener.start_listener()
+    Ok(())
 }
 
-pub fn subscribe_to_submap() -> hyprland::Result<()> {
+/// Queries recorded after each event, enough to replay every mode
+const RECORDED_QUERIES: [&str; 5] = ["workspaces", "activeworkspace", "activewindow", "monitors", "clients"];
 
-    // Create a event listener
-    let mut event_listener = EventListener::new();
+/// Records every event from the event socket, each followed by
+/// snapshots of the queries the listeners make, into the file.
+pub async fn record(file: String) -> hyprland::Result<()> {
+    let path = if file.is_empty() {
+        PathBuf::from(format!("hypr_some_events-{}.jsonl", get_timestamp()))
+    } else {
+        PathBuf::from(file)
+    };
 
-    // Shows when active window changes
-    event_listener.add_sub_map_change_handler(move |value, _| {
-        let mut output: serde_json::Value = serde_json::from_str("{}").unwrap();
+    session::start_recording(&path)?;
+    info!("Recording to {:?}", path);
 
-        let mut name = String::from("default");
-        if !value.is_empty() {
-            name = value
+    // initial state
+    get_current_submap().await;
+    exec_hyprctl_command("binds").await;
+    exec_hyprctl_command("workspacerules").await;
+    for query in RECORDED_QUERIES {
+        exec_hyprctl_command(query).await;
+    }
+
+    let mut lines = events::connect_events().await?;
+
+    while let Some(line) = lines.next_line().await? {
+        session::record_event(&line);
+        if line.starts_with("configreloaded>>") {
+            exec_hyprctl_command("binds").await;
+            exec_hyprctl_command("workspacerules").await;
+        }
+        for query in RECORDED_QUERIES {
+            exec_hyprctl_command(query).await;
         }
+    }
+
+    Ok(())
+}
+
+/// Displays the running Hyprland instances as JSON, the newest first:
+/// [{ "signature", "pid", "display", "time", "current" }]
+fn display_instances() {
+    let current = instances::get_signature().unwrap_or_default();
+
+    let list: Vec<serde_json::Value> = instances::get_instances()
+        .into_iter()
+        .map(|instance| {
+            let mut item = serde_json::Map::new();
+            item.insert(String::from("current"), serde_json::Value::Bool(instance.signature == current));
+            item.insert(String::from("signature"), serde_json::Value::String(instance.signature));
+            item.insert(String::from("pid"), serde_json::to_value(instance.pid).unwrap());
+            item.insert(String::from("display"), serde_json::to_value(instance.wayland_display).unwrap());
+            item.insert(String::from("time"), serde_json::to_value(instance.time).unwrap());
+            serde_json::Value::Object(item)
+        })
+        .collect();
+
+    output!("{}", serde_json::Value::Array(list))
+}
 
-        let output_json = output.as_object_mut();
-        if let Some(ev) = output_json {
-            ev.insert(String::from("name"), serde_json::to_value(name).unwrap());
+/// Runs the mode until it ends or the process is asked to terminate (SIGINT/SIGTERM),
+/// then flushes the output and exits with 130/143.
+pub async fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    logging::init(config.log_level, config.log_format, config.log_sink)?;
+
+    // before anything reads the signature
+    match &config.instance {
+        Instance::Current => {},
+        Instance::Signature(signature) => {
+            if !instances::is_running(signature) {
+                return Err(format!("Hyprland instance {:?} is not running (see \"hypr_some_events instances\")", signature).into())
+            }
+            instances::select(signature);
+        },
+        Instance::Newest => {
+            let signature = instances::get_newest_signature().ok_or("No running Hyprland instance")?;
+            instances::select(&signature);
+        },
+        Instance::Follow => {
+            if instances::get_newest_signature().is_none() {
+                info!("Waiting for a Hyprland instance");
+            }
+            instances::select(&instances::wait_newest().await);
         }
+    }
 
-        println!("{}", output.to_string())
-    });
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        result = run_mode(config) => result,
+        _ = sigint.recv() => output::exit(output::EXIT_SIGINT),
+        _ = sigterm.recv() => output::exit(output::EXIT_SIGTERM),
+    }
+}
+
+/// Runs the mode on the newest instance, again on the newer one when it starts
+/// or on the next one when it stops.
+async fn follow_newest_instance(config: Config) -> Result<(), Box<dyn Error>> {
+    // started once for all instances, the cache is resynced on switch
+    if config.cache {
+        start_cache(config.resync);
+    }
+
+    loop {
+        let signature = instances::wait_newest().await;
+        info!("Following Hyprland instance {}", signature);
+        instances::select(&signature);
+        cache::mark_dirty();
+
+        let notifications = config.notify.then(|| tokio::spawn(async {
+            if let Err(e) = notify_changes().await {
+                warn!("Notifications stopped: {:?}", e);
+            }
+        }));
+
+        let mode = Box::pin(run_mode(Config { instance: Instance::Current, cache: false, notify: false, ..config.clone() }));
+        tokio::select! {
+            // the listening modes only end when the event socket closes
+            result = mode => {
+                match result {
+                    Ok(()) => info!("Hyprland instance {} closed the event socket", signature),
+                    Err(e) => warn!("Hyprland instance {} stopped: {}", signature, e),
+                }
+                // it may still be shutting down, don't reconnect right away
+                tokio::time::sleep(instances::FOLLOW_INTERVAL).await;
+            },
+            _ = instances::wait_other_newest(&signature) => {},
+        }
+
+        if let Some(notifications) = notifications {
+            notifications.abort();
+        }
+    }
+}
 
-    // and execute the function
-    // here we are using the blocking variant
-    // but there is a async version too
-    event_listener.start_listener()
+/// Returns true if the mode follows the newest instance,
+/// the one-shot modes (and the served ones, which follow by themselves) just run on it.
+fn is_following(config: &Config) -> bool {
+    config.instance == Instance::Follow
+        && !config.once
+        && !matches!(
+            config.event,
+            Event::Daemon(_) | Event::Replay(_) | Event::Record | Event::Client | Event::Instances | Event::Check
+        )
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
+async fn run_mode(config: Config) -> Result<(), Box<dyn Error>> {
+    if is_following(&config) {
+        return follow_newest_instance(config).await
+    }
+
+    // recordings need the real queries, the served modes start their own
+    if config.cache && !matches!(config.event, Event::Record | Event::Daemon(_)) {
+        start_cache(config.resync);
+    }
+
+    // the replayed and served modes are run with their own config
+    if config.notify && !matches!(config.event, Event::Replay(_) | Event::Daemon(_)) {
+        tokio::spawn(async {
+            if let Err(e) = notify_changes().await {
+                error!("Notifications stopped: {:?}", e);
+            }
+        });
+    }
+
     match config.event {
         Event::Workspace => {
             if !config.ewwvar.is_empty() {
-                subscribe_to_workspace_eww(config.ewwvar)?
+                subscribe_to_workspace_eww(config.ewwvar, config.merge.unwrap_or(Merge::None), config.view).await?
+            } else if config.persistent_hyprland {
+                // live workspaces which are not persistent are shown by default
+                subscribe_to_workspace_hyprland(config.merge.unwrap_or(Merge::Append), config.view).await?
+            } else if !config.persistent.is_empty() {
+                // workspaces outside the ranges are shown by default
+                let merge = config.merge.unwrap_or(Merge::Append);
+                subscribe_to_workspace_ranges(config.persistent, config.name_pattern, config.icon_pattern, merge, config.view).await?
+            } else {
+                let rules = if config.rules.is_empty() {
+                    None
+                } else {
+                    Some(load_workspace_rules(&config.rules)?)
+                };
+                subscribe_to_workspace(config.view, rules).await?
+            }
+        },
+        Event::ActiveWorkspace => subscribe_to_active_workspace(config.json).await?,
+        Event::Submap => subscribe_to_submap(config.binds).await?,
+        Event::Windows => subscribe_to_windows().await?,
+        Event::Raw => subscribe_to_raw(config.only, config.exclude).await?,
+        Event::Record => record(config.file).await?,
+        Event::Replay(event) => {
+            if config.file.is_empty() {
+                return Err("Missing --file=<recording> to replay".into())
+            }
+            session::start_replay(Path::new(&config.file))?;
+            // replayed queries come from the recording
+            Box::pin(run_mode(Config { event: *event, cache: false, notify: false, ..config })).await?
+        },
+        Event::Daemon(event) => {
+            if matches!(*event, Event::Daemon(_) | Event::Client | Event::Check | Event::Record | Event::Invalid) {
+                return Err("Invalid mode for daemon".into())
+            }
+            // raw and windows have no state, only events (and print nothing at start)
+            daemon::start(&config.socket, matches!(*event, Event::Raw | Event::Windows)).await?;
+            Box::pin(run_mode(Config { event: *event, ..config })).await?
+        },
+        Event::Client => daemon::attach(&config.socket).await?,
+        Event::Monitors => subscribe_to_monitors().await?,
+        Event::Fullscreen => subscribe_to_fullscreen().await?,
+        Event::History => {
+            if config.once {
+                display_workspace_history().await
             } else {
-                subscribe_to_workspace()?
+                subscribe_to_workspace_history().await?
             }
         },
-        Event::ActiveWorkspace => subscribe_to_active_workspace()?,
-        Event::Submap => subscribe_to_submap()?,
-        Event::Invalid => eprintln!("Invalid argument")
+        Event::Instances => display_instances(),
+        Event::Check => check(config.ewwvar, config.rules).await?,
+        Event::Invalid => return Err("Invalid argument".into())
     };
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_move_is_displayed_once() {
+        let mut v2_filter = events::V2Filter::default();
+        let moves: Vec<_> = ["movewindow>>a,2", "movewindowv2>>a,2,2", "activewindow>>kitty,a"]
+            .iter()
+            .flat_map(|line| {
+                let (name, value) = events::parse_event(line).unwrap();
+                v2_filter.filter(name, value)
+            })
+            .filter_map(|(name, value)| get_window_event(&name, &value))
+            .collect();
+
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].0, "move");
+        assert_eq!(moves[0].1["workspace"], serde_json::json!("2"));
+    }
+}
@@ -0,0 +1,136 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Environment variable giving the log level ("off", "error", "warn", "info", "debug", "trace")
+/// when neither --verbose nor --quiet is used
+pub const LOG_ENV: &str = "HYPR_SOME_EVENTS_LOG";
+
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+const IDENTIFIER: &str = "hypr_some_events";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// "<level>: <message>"
+    Text,
+    /// { "time", "level", "target", "message" }, time in milliseconds
+    Json
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogSink {
+    Stderr,
+    /// native journald protocol, the format doesn't apply
+    Journald
+}
+
+struct Logger {
+    format: LogFormat,
+    journald: Option<UnixDatagram>,
+}
+
+/// Syslog priority of the level, as journald expects it
+fn get_priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn get_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+impl Logger {
+    fn format_line(&self, record: &Record) -> String {
+        let level = record.level().as_str().to_lowercase();
+        match self.format {
+            LogFormat::Text => format!("{}: {}", level, record.args()),
+            LogFormat::Json => {
+                let mut line = serde_json::Map::new();
+                line.insert(String::from("time"), serde_json::to_value(get_time_millis()).unwrap());
+                line.insert(String::from("level"), serde_json::Value::String(level));
+                line.insert(String::from("target"), serde_json::Value::String(String::from(record.target())));
+                line.insert(String::from("message"), serde_json::Value::String(record.args().to_string()));
+                serde_json::Value::Object(line).to_string()
+            }
+        }
+    }
+
+    /// Sends the record to journald, the message is length-prefixed so it may contain newlines
+    fn send_journald(&self, socket: &UnixDatagram, record: &Record) -> io::Result<()> {
+        let mut payload = format!(
+            "PRIORITY={}\nSYSLOG_IDENTIFIER={}\nTARGET={}\n",
+            get_priority(record.level()),
+            IDENTIFIER,
+            record.target()
+        )
+        .into_bytes();
+
+        let message = record.args().to_string();
+        payload.extend_from_slice(b"MESSAGE\n");
+        payload.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        payload.extend_from_slice(message.as_bytes());
+        payload.push(b'\n');
+
+        socket.send_to(&payload, JOURNALD_SOCKET).map(|_| ())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+
+        if let Some(socket) = &self.journald {
+            if self.send_journald(socket, record).is_ok() {
+                return
+            }
+        }
+        eprintln!("{}", self.format_line(record));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Returns the level of the environment variable, `None` if not set
+pub fn get_env_level() -> Result<Option<LevelFilter>, &'static str> {
+    match std::env::var(LOG_ENV) {
+        Ok(value) => match value.parse() {
+            Ok(level) => Ok(Some(level)),
+            Err(_) => Err("Invalid value for HYPR_SOME_EVENTS_LOG (expected off, error, warn, info, debug or trace)"),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+/// Installs the logger behind the `log` macros.
+pub fn init(level: LevelFilter, format: LogFormat, sink: LogSink) -> io::Result<()> {
+    let journald = match sink {
+        LogSink::Stderr => None,
+        LogSink::Journald => {
+            if !Path::new(JOURNALD_SOCKET).exists() {
+                return Err(io::Error::new(io::ErrorKind::NotFound, "journald socket not found"))
+            }
+            Some(UnixDatagram::unbound()?)
+        }
+    };
+
+    log::set_boxed_logger(Box::new(Logger { format, journald }))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    log::set_max_level(level);
+    Ok(())
+}
@@ -1,15 +1,16 @@
-use hypr_some_events::{Config, run};
+use hypr_some_events::{Config, output, run};
 use std::{env, process};
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let config = Config::build(env::args()).unwrap_or_else(|err| {
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        process::exit(output::EXIT_ERROR);
     });
 
     // run application
-    if let Err(e) = run(config) {
+    if let Err(e) = run(config).await {
         eprintln!("Application error: {e}");
-        process::exit(1);
+        output::exit(output::EXIT_ERROR);
     }
 }
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use zbus::zvariant::Value;
+use zbus::Connection;
+
+const APP_NAME: &str = "hypr_some_events";
+
+/// Milliseconds a notification stays, -1 lets the server decide
+const EXPIRE_TIMEOUT: i32 = -1;
+
+/// Urgency levels of the notification specification
+pub(crate) const URGENCY_NORMAL: u8 = 1;
+pub(crate) const URGENCY_CRITICAL: u8 = 2;
+
+/// Sends desktop notifications over the org.freedesktop.Notifications
+/// interface of the session bus (DBUS_SESSION_BUS_ADDRESS).
+pub(crate) struct Notifier {
+    connection: Connection,
+}
+
+impl Notifier {
+    pub(crate) async fn connect() -> zbus::Result<Notifier> {
+        Ok(Notifier {
+            connection: Connection::session().await?,
+        })
+    }
+
+    /// Shows a notification, replacing the one with id `replaces_id` if not 0.
+    /// Returns the id given by the server.
+    pub(crate) async fn notify(&self, replaces_id: u32, summary: &str, body: &str, urgency: u8) -> zbus::Result<u32> {
+        let actions: Vec<&str> = vec![];
+        let mut hints: HashMap<&str, Value> = HashMap::new();
+        hints.insert("urgency", Value::U8(urgency));
+
+        let reply = self
+            .connection
+            .call_method(
+                Some("org.freedesktop.Notifications"),
+                "/org/freedesktop/Notifications",
+                Some("org.freedesktop.Notifications"),
+                "Notify",
+                &(APP_NAME, replaces_id, "", summary, body, actions, hints, EXPIRE_TIMEOUT),
+            )
+            .await?;
+
+        reply.body().deserialize()
+    }
+}
+
+/// Notifications for submap changes and urgent windows
+pub(crate) struct Notifications {
+    notifier: Notifier,
+    last_submap: String,
+    /// submap notifications replace each other
+    submap_id: u32,
+}
+
+impl Notifications {
+    pub(crate) fn new(notifier: Notifier, submap: String) -> Notifications {
+        Notifications {
+            notifier,
+            last_submap: submap,
+            submap_id: 0,
+        }
+    }
+
+    /// Notifies entering or leaving a submap ("" or "default"), if it changed
+    pub(crate) async fn submap(&mut self, name: &str) -> zbus::Result<()> {
+        let submap = if name.is_empty() { "default" } else { name };
+        if submap == self.last_submap {
+            return Ok(())
+        }
+
+        let summary = if submap == "default" {
+            format!("Left submap {}", self.last_submap)
+        } else {
+            format!("Entered submap {}", submap)
+        };
+        self.last_submap = String::from(submap);
+        self.submap_id = self.notifier.notify(self.submap_id, &summary, "", URGENCY_NORMAL).await?;
+        Ok(())
+    }
+
+    /// Notifies a window asking for attention, described by the body
+    pub(crate) async fn urgent(&self, body: &str) -> zbus::Result<()> {
+        self.notifier.notify(0, "Urgent window", body, URGENCY_CRITICAL).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Stdio;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::process::{Child, Command};
+    use zbus::zvariant::OwnedValue;
+
+    /// (replaces_id, summary, body, urgency) of each Notify call
+    type Calls = Arc<Mutex<Vec<(u32, String, String, u8)>>>;
+
+    /// Stub org.freedesktop.Notifications server recording the calls
+    struct StubServer {
+        calls: Calls,
+        next_id: u32,
+    }
+
+    #[zbus::interface(name = "org.freedesktop.Notifications")]
+    impl StubServer {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &mut self,
+            _app_name: String,
+            replaces_id: u32,
+            _app_icon: String,
+            summary: String,
+            body: String,
+            _actions: Vec<String>,
+            hints: HashMap<String, OwnedValue>,
+            _expire_timeout: i32,
+        ) -> u32 {
+            let urgency = hints.get("urgency").and_then(|v| u8::try_from(v).ok()).unwrap_or(0);
+            self.calls.lock().unwrap().push((replaces_id, summary, body, urgency));
+            if replaces_id != 0 {
+                return replaces_id
+            }
+            self.next_id += 1;
+            self.next_id
+        }
+    }
+
+    /// Starts a private session bus, returns it with its address
+    async fn start_bus() -> Option<(Child, String)> {
+        let mut bus = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .ok()?;
+        let mut address = String::new();
+        BufReader::new(bus.stdout.take()?).read_line(&mut address).await.ok()?;
+        Some((bus, String::from(address.trim())))
+    }
+
+    #[tokio::test]
+    async fn notifies_submap_changes_and_urgent_windows() {
+        let Some((_bus, address)) = start_bus().await else {
+            eprintln!("dbus-daemon not available, skipping");
+            return
+        };
+
+        let calls = Calls::default();
+        let _server = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .name("org.freedesktop.Notifications")
+            .unwrap()
+            .serve_at("/org/freedesktop/Notifications", StubServer { calls: calls.clone(), next_id: 0 })
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+
+        let connection = zbus::connection::Builder::address(address.as_str()).unwrap().build().await.unwrap();
+        let mut notifications = Notifications::new(Notifier { connection }, String::from("default"));
+
+        notifications.submap("resize").await.unwrap();
+        notifications.submap("").await.unwrap();
+        // no change, no notification
+        notifications.submap("default").await.unwrap();
+        notifications.urgent("kitty: vim").await.unwrap();
+
+        let calls = calls.lock().unwrap().clone();
+        assert_eq!(calls, vec![
+            (0, String::from("Entered submap resize"), String::new(), URGENCY_NORMAL),
+            // replaces the previous submap notification
+            (1, String::from("Left submap resize"), String::new(), URGENCY_NORMAL),
+            (0, String::from("Urgent window"), String::from("kitty: vim"), URGENCY_CRITICAL),
+        ]);
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::process;
+use std::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Exit codes, see the README
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+/// 128 + signal number, like shells do
+pub const EXIT_SIGINT: i32 = 130;
+pub const EXIT_SIGTERM: i32 = 143;
+
+/// Same as `println!` but exits cleanly when the consumer closed stdout
+/// (e.g. eww restarted and closed the deflisten pipe) instead of panicking.
+macro_rules! output {
+    ($($arg:tt)*) => {
+        $crate::output::write_line(format_args!($($arg)*))
+    };
+}
+
+/// Receives the lines instead of stdout (daemon mode)
+static SINK: Mutex<Option<UnboundedSender<String>>> = Mutex::new(None);
+
+pub(crate) fn redirect(sender: UnboundedSender<String>) {
+    *SINK.lock().unwrap() = Some(sender);
+}
+
+pub(crate) fn write_line(args: fmt::Arguments) {
+    if let Some(sender) = SINK.lock().unwrap().as_ref() {
+        let _ = sender.send(format!("{}\n", args));
+        return
+    }
+
+    let mut stdout = io::stdout().lock();
+    let result = stdout.write_fmt(args).and_then(|_| stdout.write_all(b"\n"));
+
+    if let Err(e) = result.and_then(|_| stdout.flush()) {
+        if e.kind() == io::ErrorKind::BrokenPipe {
+            // nobody is listening anymore
+            process::exit(EXIT_OK);
+        }
+        log::error!("Could not write output: {:?}", e);
+        process::exit(EXIT_ERROR);
+    }
+}
+
+/// Flushes what was written to stdout and exits with the code.
+pub fn exit(code: i32) -> ! {
+    let _ = io::stdout().flush();
+    process::exit(code)
+}
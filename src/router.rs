@@ -0,0 +1,162 @@
+use crate::{cache, events};
+use log::trace;
+use std::io;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Events of the listener a stream can be refreshed on
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Trigger {
+    ActiveWindow,
+    Workspace,
+    WorkspaceAdded,
+    WorkspaceMoved,
+    WorkspaceDestroyed,
+    ActiveMonitor,
+    MonitorAdded,
+    MonitorRemoved,
+    Fullscreen,
+    FloatState,
+    ConfigReloaded,
+    ActiveSpecial,
+    WorkspaceRenamed,
+    WindowOpened,
+    WindowClosed,
+    WindowMoved,
+    WindowTitle,
+}
+
+/// Sent by the listener to the task refreshing the output,
+/// from the weakest to the strongest (merged refreshes keep the strongest)
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub(crate) enum Refresh {
+    /// display only if the output changed
+    IfChanged,
+    /// display anyway
+    Always,
+    /// reload what was read from the config, then display anyway
+    Reload,
+}
+
+impl Refresh {
+    /// Returns the previous active ws id to compare with, `None` to display anyway
+    pub(crate) fn previous(self, last_active: Option<i32>) -> Option<i32> {
+        match self {
+            Refresh::IfChanged => last_active,
+            Refresh::Always | Refresh::Reload => None,
+        }
+    }
+}
+
+/// An output refreshed every time one of its triggers happens
+pub(crate) trait Stream {
+    /// Events invalidating the output, and how
+    const TRIGGERS: &'static [(Trigger, Refresh)];
+
+    /// Queries and displays the output, `active_id` is the active workspace
+    /// given by the last event (workspacev2) if any, it doesn't need to be queried then
+    async fn refresh(&mut self, refresh: Refresh, active_id: Option<i32>);
+}
+
+impl Trigger {
+    /// Names of the event on the event socket, the v1 names
+    /// are only received from Hyprland versions without the v2 events
+    fn event_names(self) -> &'static [&'static str] {
+        match self {
+            Trigger::ActiveWindow => &["activewindowv2"],
+            Trigger::Workspace => &["workspacev2", "workspace"],
+            Trigger::WorkspaceAdded => &["createworkspacev2", "createworkspace"],
+            Trigger::WorkspaceMoved => &["moveworkspacev2", "moveworkspace"],
+            Trigger::WorkspaceDestroyed => &["destroyworkspacev2", "destroyworkspace"],
+            Trigger::ActiveMonitor => &["focusedmon"],
+            Trigger::MonitorAdded => &["monitoraddedv2", "monitoradded"],
+            Trigger::MonitorRemoved => &["monitorremoved"],
+            Trigger::Fullscreen => &["fullscreen"],
+            Trigger::FloatState => &["changefloatingmode"],
+            Trigger::ConfigReloaded => &["configreloaded"],
+            Trigger::ActiveSpecial => &["activespecial"],
+            Trigger::WorkspaceRenamed => &["renameworkspace"],
+            Trigger::WindowOpened => &["openwindow"],
+            Trigger::WindowClosed => &["closewindow"],
+            Trigger::WindowMoved => &["movewindowv2", "movewindow"],
+            Trigger::WindowTitle => &["windowtitlev2", "windowtitle"],
+        }
+    }
+}
+
+/// When the running refresh started, `None` while waiting for events
+static REFRESHING_SINCE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Returns for how long the running refresh has been going, `None` while waiting for events
+pub(crate) fn get_refresh_duration() -> Option<Duration> {
+    REFRESHING_SINCE.lock().unwrap().map(|since| since.elapsed())
+}
+
+/// Refreshes the stream, keeping track of how long it takes
+async fn refresh_stream<S: Stream>(stream: &mut S, refresh: Refresh, active_id: Option<i32>) {
+    REFRESHING_SINCE.lock().unwrap().replace(Instant::now());
+    stream.refresh(refresh, active_id).await;
+    REFRESHING_SINCE.lock().unwrap().take();
+}
+
+/// Waits for the next refresh and merges the ones queued meanwhile,
+/// so a burst of events only queries hyprctl once.
+/// The active workspace id is kept only if given by the last event of the burst.
+async fn next_refresh(rx: &mut mpsc::UnboundedReceiver<(Refresh, Option<i32>)>) -> Option<(Refresh, Option<i32>)> {
+    let (mut refresh, mut active_id) = rx.recv().await?;
+    while let Ok((queued, queued_active_id)) = rx.try_recv() {
+        if queued > refresh {
+            refresh = queued;
+        }
+        active_id = queued_active_id;
+    }
+    Some((refresh, active_id))
+}
+
+/// Displays the stream one time, then refreshes it on its triggers
+/// until the event socket closes.
+pub(crate) async fn run_stream<S: Stream>(mut stream: S) -> hyprland::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    // connect first so no event is missed while displaying
+    let mut lines = events::connect_events().await?;
+
+    // Display one time
+    refresh_stream(&mut stream, Refresh::Always, None).await;
+
+    let triggers = S::TRIGGERS;
+
+    // the listener runs in its own task so it keeps reading events while hyprctl is queried,
+    // it updates the cache before the refresh so the refresh sees the event
+    let listener = tokio::spawn(async move {
+        let mut v2_filter = events::V2Filter::default();
+        while let Some(line) = lines.next_line().await? {
+            trace!("event {}", line);
+            let Some((name, data)) = events::parse_event(&line) else { continue };
+            for (name, data) in v2_filter.filter(name, data) {
+                cache::apply(&name, &data);
+                let active_id = match name.as_str() {
+                    "workspacev2" => data["id"].as_str().and_then(|id| id.parse().ok()),
+                    _ => None,
+                };
+                for (trigger, refresh) in triggers {
+                    if trigger.event_names().contains(&name.as_str()) {
+                        // the refresh task only stops with the program
+                        let _ = tx.send((*refresh, active_id));
+                    }
+                }
+            }
+        }
+        Ok::<(), io::Error>(())
+    });
+
+    while let Some((refresh, active_id)) = next_refresh(&mut rx).await {
+        refresh_stream(&mut stream, refresh, active_id).await;
+    }
+
+    match listener.await {
+        Ok(result) => Ok(result?),
+        Err(e) => Err(io::Error::other(e).into()),
+    }
+}
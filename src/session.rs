@@ -0,0 +1,171 @@
+use crate::instances;
+use log::{error, warn};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Longest pause between two replayed events
+const REPLAY_MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Shortest pause between two replayed events,
+/// gives the listener time to query the snapshots of the previous event
+const REPLAY_MIN_DELAY: Duration = Duration::from_millis(20);
+
+/// A recorded "hyprctl -j <command>" output,
+/// visible once "position" events have been replayed
+struct Snapshot {
+    position: usize,
+    command: String,
+    output: serde_json::Value,
+}
+
+enum Session {
+    Record {
+        file: File,
+        start: Instant,
+    },
+    Replay {
+        snapshots: Vec<Snapshot>,
+        position: usize,
+    },
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// Starts writing events and query snapshots to the file, one JSON object per line:
+/// { "time", "event" } or { "time", "query", "output" } with time in milliseconds.
+pub(crate) fn start_recording(path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    SESSION.lock().unwrap().replace(Session::Record {
+        file,
+        start: Instant::now(),
+    });
+    Ok(())
+}
+
+fn record(entry: serde_json::Map<String, serde_json::Value>) {
+    if let Some(Session::Record { file, start }) = SESSION.lock().unwrap().as_mut() {
+        let mut entry = entry;
+        entry.insert(
+            String::from("time"),
+            serde_json::to_value(start.elapsed().as_millis() as u64).unwrap(),
+        );
+        if let Err(e) = writeln!(file, "{}", serde_json::Value::Object(entry)) {
+            error!("Could not write recording: {:?}", e);
+        }
+    }
+}
+
+/// Records a line from the event socket if recording.
+pub(crate) fn record_event(line: &str) {
+    let mut entry = serde_json::Map::new();
+    entry.insert(String::from("event"), serde_json::to_value(line).unwrap());
+    record(entry);
+}
+
+/// Records the output of "hyprctl -j <command>" if recording.
+pub(crate) fn record_query(command: &str, output: &serde_json::Value) {
+    let mut entry = serde_json::Map::new();
+    entry.insert(String::from("query"), serde_json::to_value(command).unwrap());
+    entry.insert(String::from("output"), output.clone());
+    record(entry);
+}
+
+/// Returns the recorded output of "hyprctl -j <command>" if replaying,
+/// `Some(Value::Null)` if it was never recorded up to the current event.
+pub(crate) fn replayed_query(command: &str) -> Option<serde_json::Value> {
+    if let Some(Session::Replay { snapshots, position }) = SESSION.lock().unwrap().as_ref() {
+        let output = snapshots
+            .iter()
+            .rev()
+            .find(|s| s.position <= *position && s.command == command)
+            .map(|s| s.output.clone())
+            .unwrap_or(serde_json::Value::Null);
+        return Some(output)
+    }
+    None
+}
+
+pub(crate) fn is_replaying() -> bool {
+    matches!(SESSION.lock().unwrap().as_ref(), Some(Session::Replay { .. }))
+}
+
+/// Loads a recording and serves its events on a fake Hyprland event socket
+/// (selected as the Hyprland instance),
+/// so listeners process them as if they came from the compositor.
+/// The socket is closed after the last event, which ends the listener.
+pub(crate) fn start_replay(path: &Path) -> io::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut events: Vec<(u64, String)> = vec![];
+    let mut snapshots: Vec<Snapshot> = vec![];
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: serde_json::Value = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", index + 1, e))
+        })?;
+        let time = entry.get("time").and_then(|v| v.as_u64()).unwrap_or(0);
+
+        if let Some(event) = entry.get("event").and_then(|v| v.as_str()) {
+            events.push((time, String::from(event)));
+        } else if let Some(command) = entry.get("query").and_then(|v| v.as_str()) {
+            snapshots.push(Snapshot {
+                position: events.len(),
+                command: String::from(command),
+                output: entry.get("output").cloned().unwrap_or(serde_json::Value::Null),
+            });
+        } else {
+            warn!("Ignoring recording line {}: neither an event nor a query", index + 1);
+        }
+    }
+
+    // fake instance, in the directory the listener looks into
+    let signature = format!("hypr_some_events_replay_{}", std::process::id());
+    let dir = PathBuf::from("/tmp/hypr").join(&signature);
+    fs::create_dir_all(&dir)?;
+    let socket_path = dir.join(".socket2.sock");
+    let _ = fs::remove_file(&socket_path);
+    let listener = UnixListener::bind(&socket_path)?;
+
+    instances::select(&signature);
+
+    SESSION.lock().unwrap().replace(Session::Replay {
+        snapshots,
+        position: 0,
+    });
+
+    thread::spawn(move || {
+        if let Ok((mut stream, _)) = listener.accept() {
+            let mut last_time = events.first().map(|(time, _)| *time).unwrap_or(0);
+            for (index, (time, line)) in events.iter().enumerate() {
+                let delay = Duration::from_millis(time.saturating_sub(last_time));
+                thread::sleep(delay.clamp(REPLAY_MIN_DELAY, REPLAY_MAX_DELAY));
+                last_time = *time;
+
+                // snapshots taken after this event become visible
+                if let Some(Session::Replay { position, .. }) = SESSION.lock().unwrap().as_mut() {
+                    *position = index + 1;
+                }
+
+                if let Err(e) = writeln!(stream, "{}", line) {
+                    error!("Could not replay event: {:?}", e);
+                    break;
+                }
+            }
+            // let the listener process the last event before closing
+            thread::sleep(REPLAY_MAX_DELAY);
+            let _ = fs::remove_dir_all(&dir);
+        }
+    });
+
+    Ok(())
+}